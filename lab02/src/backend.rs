@@ -3,6 +3,7 @@
 //! le routeur, et les middlewares.
 pub mod handlers_auth;
 mod models;
-mod middlewares;
+pub mod middlewares;
 pub mod router;
 pub mod handlers_unauth;
+pub mod handlers_dev;
@@ -1,60 +1,240 @@
 //! Gestion des routes nécessitant une authentification utilisateur.
 
 use axum::{
-    extract::{Multipart, Query},
-    response::{Html, IntoResponse},
+    body::Body,
+    extract::{Multipart, Path as UrlPath, Query},
+    response::{Html, IntoResponse, Response},
     Json, Extension,
 };
-use anyhow::anyhow;
 use handlebars::Handlebars;
-use http::StatusCode;
-use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
+use log::error;
+use http::{header, HeaderMap, StatusCode};
 use serde_json::json;
-use std::{
-    collections::HashMap,
-    fs::{create_dir_all, File},
-    io::Write,
-    path::Path,
-    sync::{Arc, RwLock},
-};
+use std::{collections::HashMap, sync::Arc};
+use tokio_util::io::ReaderStream;
 use uuid::Uuid;
-use crate::consts;
-use crate::utils::input::validate_image_file;
+use webauthn_rs::prelude::RegisterPublicKeyCredential;
+use crate::{consts, media};
+use crate::backend::handlers_unauth::{take_registration_state, Ceremony, REGISTRATION_STATES};
+use crate::backend::middlewares::SessionUser;
+use crate::backend::models::{CommentsQuery, FeedQuery, SearchQuery, WebAuthnChallenge};
+use crate::database::comment::{self, Comment};
+use crate::database::post::{self, Filter, Post, Reaction};
+use crate::database::user::{self, Credential};
+use crate::utils::input::{
+    is_valid_comment_content, is_valid_passkey_name, is_valid_post_content, validate_image_file,
+};
+use crate::utils::text::{extract_mentions, extract_tags, is_valid_tag};
+use crate::utils::webauthn::{begin_registration, complete_registration, StoredRegistrationState};
 
-/// Modèle représentant un post avec des likes
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct Post {
-    pub id: Uuid,
-    pub content: String,
-    pub image_path: Option<String>,
-    pub likes: i32,
+/// Affiche la page principale avec une page du fil d'actualité
+pub async fn home(
+    Extension(hbs): Extension<Arc<Handlebars<'_>>>,
+    SessionUser { email }: SessionUser,
+    Query(query): Query<FeedQuery>,
+) -> axum::response::Result<Html<String>> {
+    Ok(render_feed(&hbs, &email, &query, Filter::All, "/home", None)?)
 }
 
-/// Base de données statique pour les posts (simulée en mémoire)
-static POSTS: Lazy<RwLock<Vec<Post>>> = Lazy::new(|| {
-    RwLock::new(vec![])
-});
+/// Affiche les posts portant un hashtag
+pub async fn tag_feed(
+    Extension(hbs): Extension<Arc<Handlebars<'_>>>,
+    SessionUser { email }: SessionUser,
+    UrlPath(tag): UrlPath<String>,
+    Query(query): Query<FeedQuery>,
+) -> axum::response::Result<Html<String>> {
+    let tag = tag.to_lowercase();
+    if !is_valid_tag(&tag) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid hashtag").into());
+    }
 
-/// Affiche la page principale avec la liste des posts
-pub async fn home(
+    let heading = format!("#{}", tag);
+    Ok(render_feed(&hbs, &email, &query, Filter::Tag(&tag), &format!("/tag/{}", tag), Some(&heading))?)
+}
+
+/// Affiche les posts mentionnant l'utilisateur connecté
+pub async fn mentions_page(
     Extension(hbs): Extension<Arc<Handlebars<'_>>>,
-    Query(params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    let user = params.get("user").cloned().unwrap_or_else(|| "Guest".to_string());
-    let data = json!({
-        "user": user,
-        "posts": *POSTS.read().unwrap(),
-    });
+    SessionUser { email }: SessionUser,
+    Query(query): Query<FeedQuery>,
+) -> axum::response::Result<Html<String>> {
+    Ok(render_feed(&hbs, &email, &query, Filter::Mentioning(&email), "/mentions", Some("Mentions"))?)
+}
+
+/// Affiche les posts enregistrés par l'utilisateur connecté
+pub async fn bookmarks_page(
+    Extension(hbs): Extension<Arc<Handlebars<'_>>>,
+    SessionUser { email }: SessionUser,
+    Query(query): Query<FeedQuery>,
+) -> axum::response::Result<Html<String>> {
+    let stash = user::get(&email).map(|u| u.stash).unwrap_or_default();
+    Ok(render_feed(&hbs, &email, &query, Filter::Bookmarked(&stash), "/bookmarks", Some("Saved posts"))?)
+}
+
+/// Affiche une page d'un fil avec le template de la page principale
+fn render_feed(
+    hbs: &Handlebars<'_>,
+    email: &str,
+    query: &FeedQuery,
+    filter: Filter,
+    base_path: &str,
+    heading: Option<&str>,
+) -> Result<Html<String>, (StatusCode, &'static str)> {
+    let mut data = feed_page(email, query, filter)?;
+    data["user"] = json!(email);
+    data["sort"] = json!(query.sort);
+    data["base_path"] = json!(base_path);
+    data["heading"] = json!(heading);
 
     match hbs.render("home", &data) {
-        Ok(body) => Html(body),
-        Err(_) => Html("<h1>Internal Server Error</h1>".to_string()),
+        Ok(body) => Ok(Html(body)),
+        Err(_) => Ok(Html("<h1>Internal Server Error</h1>".to_string())),
     }
 }
 
+/// Retourne une page du fil d'actualité en JSON (défilement infini), éventuellement
+/// limitée à un hashtag
+pub async fn feed(
+    SessionUser { email }: SessionUser,
+    Query(query): Query<FeedQuery>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let tag = query.tag.as_deref().map(str::to_lowercase);
+    let filter = match &tag {
+        Some(tag) if !is_valid_tag(tag) => return Err((StatusCode::BAD_REQUEST, "Invalid hashtag").into()),
+        Some(tag) => Filter::Tag(tag),
+        None => Filter::All,
+    };
+
+    Ok(Json(feed_page(&email, &query, filter)?))
+}
+
+/// Recherche plein texte dans les posts, résultats classés par pertinence
+pub async fn search_posts(
+    SessionUser { email }: SessionUser,
+    Query(query): Query<SearchQuery>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    if query.q.trim().is_empty() || query.q.len() > 200 {
+        return Err((StatusCode::BAD_REQUEST, "Query must be between 1 and 200 characters").into());
+    }
+
+    let results = post::search(&query.q, query.offset, consts::SEARCH_PAGE_SIZE)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Search failed"))?;
+
+    let viewer = Viewer::load(&email);
+    let mut authors = HashMap::new();
+    let posts: Vec<_> = results
+        .posts
+        .iter()
+        .map(|p| post_view(p, &viewer, &mut authors))
+        .collect();
+
+    let next_offset = query.offset + consts::SEARCH_PAGE_SIZE;
+    Ok(Json(json!({
+        "posts": posts,
+        "total": results.total,
+        "next_offset": (next_offset < results.total).then_some(next_offset),
+    })))
+}
+
+/// Charge une page du fil et prépare ses posts pour l'affichage
+fn feed_page(email: &str, query: &FeedQuery, filter: Filter) -> Result<serde_json::Value, (StatusCode, &'static str)> {
+    let page = post::page(filter, query.sort, query.cursor.as_deref(), consts::FEED_PAGE_SIZE)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid cursor"))?;
+
+    let viewer = Viewer::load(email);
+    let mut authors = HashMap::new();
+    let posts: Vec<_> = page
+        .posts
+        .iter()
+        .map(|p| post_view(p, &viewer, &mut authors))
+        .collect();
+
+    Ok(json!({
+        "posts": posts,
+        "next_cursor": page.next_cursor,
+    }))
+}
+
+/// Utilisateur connecté, avec ses réactions et favoris pour l'affichage des posts
+struct Viewer {
+    email: String,
+    reactions: HashMap<Uuid, Reaction>,
+    stash: Vec<String>,
+}
+
+impl Viewer {
+    fn load(email: &str) -> Self {
+        let user = user::get(email);
+        Viewer {
+            email: email.to_string(),
+            reactions: user.as_ref().map(|u| u.reactions.clone()).unwrap_or_default(),
+            stash: user.map(|u| u.stash).unwrap_or_default(),
+        }
+    }
+}
+
+/// Prépare un post pour l'affichage, avec le nom de son auteur, la réaction
+/// de l'utilisateur courant et s'il l'a enregistré
+fn post_view(post: &Post, viewer: &Viewer, authors: &mut HashMap<String, String>) -> serde_json::Value {
+    let author_name = author_name(authors, &post.author);
+    let image_id = post.image_path.as_deref().and_then(media::id_from_path);
+
+    json!({
+        "id": post.id,
+        "author": post.author,
+        "is_own": post.author == viewer.email,
+        "author_name": author_name,
+        "content": post.content,
+        "image_url": image_id.as_ref().map(|id| format!("/media/{}", id)),
+        "thumbnail_url": image_id.as_deref().map(|id| format!("/media/{}", media::preview_id(id))),
+        "like_count": post.like_count,
+        "dislike_count": post.dislike_count,
+        "comment_count": post.comment_count,
+        "tags": post.tags,
+        "reaction": viewer.reactions.get(&post.id),
+        "bookmarked": viewer.stash.contains(&post.id.to_string()),
+        "created_at": post.created_at.format("%Y-%m-%d %H:%M").to_string(),
+        "edited_at": post.edited_at.map(|d| d.format("%Y-%m-%d %H:%M").to_string()),
+    })
+}
+
+/// Hashtags et mentions d'un texte de post. Seules les mentions d'utilisateurs existants
+/// sont conservées.
+fn post_references(text: &str) -> (Vec<String>, Vec<String>) {
+    let mentions = extract_mentions(text)
+        .into_iter()
+        .filter(|email| user::exists(email).unwrap_or(false))
+        .collect();
+    (extract_tags(text), mentions)
+}
+
+/// Nom affiché d'un auteur, mis en cache pour le rendu d'une page
+fn author_name(authors: &mut HashMap<String, String>, email: &str) -> String {
+    authors
+        .entry(email.to_string())
+        .or_insert_with(|| {
+            user::get(email)
+                .map(|u| format!("{} {}", u.first_name, u.last_name))
+                .unwrap_or_else(|| "Unknown user".to_string())
+        })
+        .clone()
+}
+
+/// Prépare un commentaire pour l'affichage
+fn comment_view(comment: &Comment, authors: &mut HashMap<String, String>) -> serde_json::Value {
+    json!({
+        "id": comment.id,
+        "author_name": author_name(authors, &comment.author),
+        "content": comment.content,
+        "created_at": comment.created_at.format("%Y-%m-%d %H:%M").to_string(),
+    })
+}
+
 /// Crée un nouveau post avec texte et image
-pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Json<serde_json::Value>> {
+pub async fn create_post(
+    SessionUser { email }: SessionUser,
+    mut multipart: Multipart,
+) -> axum::response::Result<Json<serde_json::Value>> {
     let mut text_content = None;
     let mut uploaded_file_path = None;
 
@@ -65,140 +245,449 @@ pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Jso
             let text = field.text().await.unwrap_or_default();
 
             // Validate text content
-            if text.is_empty() || text.len() > 200 {
+            if !is_valid_post_content(&text) {
                 return Err((StatusCode::BAD_REQUEST, "Text must be between 1 and 200 characters").into());
             }
 
             text_content = Some(text);
         } else if field_name == "file" {
-            let filename = field.file_name().unwrap_or_default().to_string();
             let content_type = field.content_type().map(|ct| ct.to_string()).unwrap_or_default();
             let file_bytes = field.bytes().await?;
 
             // Validate file type
-            validate_image_file(&content_type, &file_bytes)?;
-
-            let uploads_dir = consts::UPLOADS_DIR;
-            if !Path::new(uploads_dir).exists() {
-                create_dir_all(uploads_dir).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create upload directory"))?;
-            }
+            let format = validate_image_file(&content_type, &file_bytes)?;
 
-            // Generate unique filename to prevent overwriting
-            let file_extension = Path::new(&filename)
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .unwrap_or("jpg");
-            let unique_filename = format!("{}.{}", Uuid::new_v4(), file_extension);
-            let file_path = format!("{}/{}", uploads_dir, unique_filename);
-
-            // Save the file
-            let mut file = File::create(&file_path)
-                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create file"))?;
-            file.write_all(&file_bytes)
-                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to write file"))?;
+            // Ré-encodage de l'image (suppression des métadonnées, réduction, miniature)
+            let path = tokio::task::spawn_blocking(move || {
+                let image = media::process(&file_bytes, format)?;
+                media::save(&image)
+            })
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to process image"))?
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid image file"))?;
 
             // Chemin relatif utilisé par le frontend
-            uploaded_file_path = Some(format!("{}/{}", consts::UPLOADS_DIR, unique_filename));
+            uploaded_file_path = Some(path);
         }
     }
 
     let text = text_content.ok_or((StatusCode::BAD_REQUEST, "Text content is required"))?;
     let image_path = uploaded_file_path;
 
-    let post_id = save_post(&text, image_path.as_deref());
+    let (tags, mentions) = post_references(&text);
+    let post_id = post::create(&email, &text, image_path.as_deref(), tags, mentions)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save post"))?;
 
-    Ok(Json(json!({ "post_id": post_id })))
+    Ok(Json(json!({ "post_id": post_id.to_string() })))
 }
 
-/// Sauvegarde des posts dans un fichier YAML
-pub fn save_posts_to_file() -> Result<(), anyhow::Error> {
-    let posts = POSTS.read().map_err(|_| anyhow!("Failed to read posts"))?; // Lecture des posts existants
-    let file_path = consts::POSTS_DB_PATH;
-    let file_dir = Path::new(file_path).parent().unwrap();
-
-    if !file_dir.exists() {
-        create_dir_all(file_dir).or(Err(anyhow!("Failed to create directory for posts.")))?;
+/// Sert une image uploadée aux utilisateurs connectés
+///
+/// Les fichiers ne sont jamais modifiés après leur création: l'ETag est dérivé de la
+/// taille et de la date de modification, et la réponse peut être mise en cache.
+pub async fn serve_media(
+    UrlPath(id): UrlPath<String>,
+    headers: HeaderMap,
+) -> axum::response::Result<Response> {
+    let not_found = (StatusCode::NOT_FOUND, "Media not found");
+    let content_type = media::content_type(&id).ok_or(not_found)?;
+    let path = media::resolve(&id).ok_or(not_found)?;
+
+    let file = tokio::fs::File::open(&path).await.map_err(|_| not_found)?;
+    let metadata = file.metadata().await.map_err(|_| not_found)?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", metadata.len(), modified);
+
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, "private, max-age=31536000, immutable".to_string()),
+    ];
+
+    let matches = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if matches {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
     }
 
-    let file = File::create(file_path).or(Err(anyhow!("Failed to create posts.yaml.")))?;
-    serde_yaml::to_writer(file, &*posts).or(Err(anyhow!("Failed to serialize posts to YAML.")))?;
-    Ok(())
+    Ok((
+        cache_headers,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, metadata.len().to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
 }
 
-/// Charge les posts depuis un fichier YAML
-pub fn load_posts_from_file() -> Result<(), anyhow::Error> {
-    let file_path = consts::POSTS_DB_PATH;
+/// Lit l'identifiant du post dans le corps de la requête
+fn post_id_from_body(body: &serde_json::Value) -> Result<Uuid, (StatusCode, &'static str)> {
+    let post_id = body
+        .get("post_id")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Post ID is required"))?;
+    Uuid::parse_str(post_id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Post ID"))
+}
+
+/// Vérifie que le post existe et appartient à l'utilisateur connecté
+fn owned_post(email: &str, post_id: Uuid) -> Result<Post, (StatusCode, &'static str)> {
+    let post = post::get(post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load post"))?
+        .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+
+    if post.author != email {
+        return Err((StatusCode::FORBIDDEN, "You can only modify your own posts"));
+    }
+    Ok(post)
+}
 
-    if Path::new(file_path).exists() {
-        let file = File::open(file_path).or(Err(anyhow!("Failed to open posts.yaml.")))?;
-        let loaded_posts: Vec<Post> = serde_yaml::from_reader(file).unwrap_or_default();
+/// Modifie le texte d'un post de l'utilisateur connecté
+pub async fn edit_post(
+    SessionUser { email }: SessionUser,
+    Json(body): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let post_id = post_id_from_body(&body)?;
+    let text = body
+        .get("text")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Text content is required"))?;
 
-        let mut posts = POSTS.write().map_err(|_| anyhow!("Failed to write posts"))?;
-        *posts = loaded_posts;
+    if !is_valid_post_content(text) {
+        return Err((StatusCode::BAD_REQUEST, "Text must be between 1 and 200 characters").into());
     }
 
-    Ok(())
+    owned_post(&email, post_id)?;
+
+    let (tags, mentions) = post_references(text);
+    let found = post::edit(post_id, text, tags, mentions)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save post"))?;
+
+    if found {
+        return Ok(StatusCode::OK);
+    }
+
+    Err((StatusCode::NOT_FOUND, "Post not found").into())
+}
+
+/// Supprime un post de l'utilisateur connecté et libère son image
+pub async fn delete_post(
+    SessionUser { email }: SessionUser,
+    Json(body): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let post_id = post_id_from_body(&body)?;
+    owned_post(&email, post_id)?;
+
+    // L'image peut être partagée avec d'autres posts (même contenu): elle n'est libérée
+    // que si le post supprimé était le dernier à la référencer. En cas d'échec, le
+    // nettoyage périodique la supprimera.
+    tokio::task::spawn_blocking(move || {
+        post::delete(post_id, &mut |path| {
+            if let Err(e) = media::release(path) {
+                error!("Failed to release image {}: {}", path, e);
+            }
+        })
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete post"))?
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete post"))?
+    .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+
+    Ok(StatusCode::OK)
 }
 
-/// Simule la sauvegarde d'un post dans une base de données
-fn save_post(text: &str, image_path: Option<&str>) -> String {
-    let new_post = Post {
-        id: Uuid::new_v4(),
-        content: text.to_string(),
-        image_path: image_path.map(|path| path.to_string()),
-        likes: 0,
+/// Ajoute un commentaire à un post, ou une réponse à un commentaire
+pub async fn comment_post(
+    SessionUser { email }: SessionUser,
+    Json(body): Json<serde_json::Value>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let post_id = post_id_from_body(&body)?;
+    let parent_id = match body.get("parent_id").and_then(|v| v.as_str()) {
+        Some(id) => Some(Uuid::parse_str(id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid parent ID"))?),
+        None => None,
     };
+    let text = body
+        .get("text")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Text content is required"))?;
 
-    let post_id = new_post.id.to_string();
+    if !is_valid_comment_content(text) {
+        return Err((StatusCode::BAD_REQUEST, "Comment must be between 1 and 500 characters").into());
+    }
 
+    let comment_id = match comment::create(&email, post_id, parent_id, text)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to add comment"))?
     {
-        let mut posts = POSTS.write().unwrap();
-        posts.push(new_post);
-    }
+        comment::Outcome::Created(id) => id,
+        comment::Outcome::PostNotFound => return Err((StatusCode::NOT_FOUND, "Post not found").into()),
+        comment::Outcome::InvalidParent => return Err((StatusCode::BAD_REQUEST, "Invalid parent comment").into()),
+    };
 
-    if let Err(e) = save_posts_to_file() {
-        eprintln!("Failed to save posts: {}", e);
-    }
+    Ok(Json(json!({ "comment_id": comment_id.to_string() })))
+}
 
-    post_id
+/// Retourne une page de commentaires d'un post, chacun avec ses réponses
+pub async fn post_comments(
+    UrlPath(post_id): UrlPath<Uuid>,
+    Query(query): Query<CommentsQuery>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    post::get(post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load post"))?
+        .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+
+    let comments = comment::for_post(post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load comments"))?;
+
+    let (top_level, replies): (Vec<_>, Vec<_>) = comments.iter().partition(|c| c.parent_id.is_none());
+    let mut authors = HashMap::new();
+
+    let page: Vec<_> = top_level
+        .iter()
+        .skip(query.offset)
+        .take(consts::COMMENTS_PAGE_SIZE)
+        .map(|c| {
+            let mut view = comment_view(c, &mut authors);
+            view["replies"] = replies
+                .iter()
+                .filter(|r| r.parent_id == Some(c.id))
+                .map(|r| comment_view(r, &mut authors))
+                .collect();
+            view
+        })
+        .collect();
+
+    let next_offset = query.offset + page.len();
+    Ok(Json(json!({
+        "comments": page,
+        "next_offset": (next_offset < top_level.len()).then_some(next_offset),
+    })))
 }
 
-/// Permet de like un post
-pub async fn like_post(Json(body): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
-    let post_id = body
-        .get("post_id")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Post ID is required"))?;
-    let post_id = Uuid::parse_str(post_id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
+/// Enregistre un post dans les favoris, ou l'en retire s'il y est déjà
+pub async fn bookmark_post(
+    SessionUser { email }: SessionUser,
+    Json(body): Json<serde_json::Value>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let post_id = post_id_from_body(&body)?;
+
+    post::get(post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load post"))?
+        .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+
+    let bookmarked = user::toggle_bookmark(&email, post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save bookmark"))?;
+
+    Ok(Json(json!({ "bookmarked": bookmarked })))
+}
+
+/// Permet de like ou dislike un post. Répéter la même action annule la réaction.
+pub async fn like_post(
+    SessionUser { email }: SessionUser,
+    Json(body): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let post_id = post_id_from_body(&body)?;
 
     let action = body
         .get("action")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Action is required"))?;
-
-    let mut posts = POSTS.write().map_err(|_| (StatusCode::BAD_REQUEST, "Failed to write posts"))?;
-    let post = posts.iter_mut().find(|post| post.id == post_id);
-
-    if let Some(post) = post {
-        match action {
-            "like" => {
-                if post.likes == 1 {
-                    post.likes = 0;
-                } else {
-                    post.likes = 1;
-                }
-            }
-            "dislike" => {
-                if post.likes == -1 {
-                    post.likes = 0;
-                } else {
-                    post.likes = -1;
-                }
-            }
-            _ => return Err((StatusCode::BAD_REQUEST, "Invalid action").into()),
-        }
+        .ok_or((StatusCode::BAD_REQUEST, "Action is required"))?;
+
+    let reaction = match action {
+        "like" => Reaction::Like,
+        "dislike" => Reaction::Dislike,
+        _ => return Err((StatusCode::BAD_REQUEST, "Invalid action").into()),
+    };
+
+    let found = post::react(&email, post_id, reaction)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save reaction"))?;
+
+    if found {
         return Ok(StatusCode::OK);
     }
 
     Err((StatusCode::NOT_FOUND, "Post not found").into())
 }
+
+/// Affiche la page de gestion des passkeys de l'utilisateur
+pub async fn passkeys_page(
+    Extension(hbs): Extension<Arc<Handlebars<'_>>>,
+    SessionUser { email }: SessionUser,
+) -> axum::response::Result<Html<String>> {
+    let credentials = user::get_credentials(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load passkeys"))?;
+
+    let passkeys: Vec<_> = credentials
+        .iter()
+        .map(|c| json!({
+            "id": c.id(),
+            "name": c.name,
+            "created_at": c.created_at.format("%Y-%m-%d %H:%M").to_string(),
+            "last_used_at": c.last_used_at.map(|d| d.format("%Y-%m-%d %H:%M").to_string()),
+        }))
+        .collect();
+
+    let data = json!({
+        "email": email,
+        "passkeys": passkeys,
+        "can_revoke": credentials.len() > 1,
+    });
+
+    hbs.render("passkeys", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error.").into())
+}
+
+/// Début de l'ajout d'une passkey supplémentaire au compte
+pub async fn passkey_add_begin(
+    SessionUser { email }: SessionUser,
+) -> axum::response::Result<Json<WebAuthnChallenge>> {
+    let user = user::get(&email).ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+    let existing = user
+        .credentials
+        .iter()
+        .map(|c| c.passkey.cred_id().clone())
+        .collect();
+
+    let (public_key, pskr) = begin_registration(user.user_handle, &email, &email, existing)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let state_id = Uuid::new_v4().to_string();
+    REGISTRATION_STATES.write().await.insert(
+        state_id.clone(),
+        Ceremony::new(StoredRegistrationState {
+            registration_state: pskr,
+            email,
+            user_handle: user.user_handle,
+        }),
+    );
+
+    Ok(Json(WebAuthnChallenge {
+        challenge: public_key,
+        state_id,
+    }))
+}
+
+/// Fin de l'ajout d'une passkey supplémentaire au compte
+pub async fn passkey_add_complete(
+    SessionUser { email }: SessionUser,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let name = payload
+        .get("name")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Name is required"))?;
+    if !is_valid_passkey_name(name) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid passkey name").into());
+    }
+
+    let state_id = payload
+        .get("state_id")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "State ID is required"))?;
+
+    let response: RegisterPublicKeyCredential = serde_json::from_value(
+        payload
+            .get("response")
+            .cloned()
+            .ok_or((StatusCode::BAD_REQUEST, "Response is required"))?,
+    )
+    .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid response format"))?;
+
+    let stored_state = take_registration_state(state_id)
+        .await
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid registration session"))?;
+
+    let passkey = complete_registration(&email, &response, &stored_state)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    user::add_credential(&email, Credential::new(name.trim(), passkey))
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    Ok(StatusCode::CREATED)
+}
+
+/// Renomme une passkey de l'utilisateur
+pub async fn passkey_rename(
+    SessionUser { email }: SessionUser,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let id = payload
+        .get("id")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Passkey ID is required"))?;
+    let name = payload
+        .get("name")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Name is required"))?;
+    if !is_valid_passkey_name(name) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid passkey name").into());
+    }
+
+    user::rename_credential(&email, id, name.trim())
+        .map_err(|_| (StatusCode::NOT_FOUND, "Passkey not found"))?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Révoque une passkey de l'utilisateur
+pub async fn passkey_revoke(
+    SessionUser { email }: SessionUser,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let id = payload
+        .get("id")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Passkey ID is required"))?;
+
+    user::revoke_credential(&email, id)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{unique_email, Authenticator, Client};
+
+    #[tokio::test]
+    async fn last_passkey_cannot_be_revoked() {
+        let mut client = Client::new();
+        let mut authenticator = Authenticator::new();
+        let email = unique_email("revoke");
+        assert_eq!(client.register(&mut authenticator, &email, false).await, StatusCode::CREATED);
+        assert_eq!(client.login(&mut authenticator, &email).await, StatusCode::SEE_OTHER);
+
+        let first = user::get(&email).unwrap().credentials[0].id();
+        let (status, _) = client.post_json("/passkeys/revoke", json!({ "id": first })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(user::get(&email).unwrap().credentials.len(), 1);
+
+        // Avec une seconde passkey, la première peut être révoquée
+        let (_, body) = client.post_json("/passkeys/add", json!({})).await;
+        let challenge: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let response = Authenticator::new().register(&challenge["publicKey"]);
+        let (status, _) = client
+            .post_json(
+                "/passkeys/add/complete",
+                json!({ "name": "Backup key", "state_id": challenge["state_id"], "response": response }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _) = client.post_json("/passkeys/revoke", json!({ "id": first })).await;
+        assert_eq!(status, StatusCode::OK);
+        let credentials = user::get(&email).unwrap().credentials;
+        assert_eq!(credentials.len(), 1);
+        assert_eq!(credentials[0].name, "Backup key");
+    }
+}
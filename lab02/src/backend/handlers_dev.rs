@@ -0,0 +1,76 @@
+//! Outils de développement, disponibles uniquement en mode debug ou si la configuration
+//! les active explicitement (voir [`crate::backend::router::get_router`]).
+
+use axum::{extract::Query, response::Html};
+use handlebars::html_escape;
+use http::StatusCode;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use crate::database::email;
+use crate::HBS;
+
+/// Liens dans la version texte d'un email
+static LINK_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"https?://[^\s<>"']+"#).unwrap()
+});
+
+/// Page de la boîte aux lettres à afficher
+#[derive(Deserialize)]
+pub struct MailboxQuery {
+    #[serde(default)]
+    pub page: usize, // 0 pour le message le plus récent
+}
+
+/// Échappe un texte et rend ses liens cliquables
+fn linkify(text: &str) -> String {
+    LINK_REGEX
+        .replace_all(&html_escape(text), |captures: &regex::Captures| {
+            format!(r#"<a href="{0}" target="_blank" rel="noopener">{0}</a>"#, &captures[0])
+        })
+        .into_owned()
+}
+
+/// Affiche les emails enregistrés, un message par page, du plus récent au plus ancien
+pub async fn mailbox(Query(query): Query<MailboxQuery>) -> axum::response::Result<Html<String>> {
+    let mut emails = email::all()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load emails"))?;
+    emails.reverse();
+
+    let list: Vec<_> = emails
+        .iter()
+        .enumerate()
+        .map(|(page, e)| json!({
+            "page": page,
+            "to": e.to,
+            "subject": e.subject,
+            "status": e.status,
+            "current": page == query.page,
+        }))
+        .collect();
+
+    let message = emails.get(query.page).map(|e| json!({
+        "pk": e.pk,
+        "to": e.to,
+        "subject": e.subject,
+        "status": e.status,
+        "attempts": e.attempts,
+        "last_error": e.last_error,
+        "created_at": e.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        "text": linkify(&e.body),
+        // Les liens de la version HTML s'ouvrent hors de l'iframe
+        "html": e.html.as_ref().map(|html| format!("<base target=\"_blank\">{}", html)),
+    }));
+
+    let data = json!({
+        "emails": list,
+        "message": message,
+        "previous": query.page.checked_sub(1).filter(|_| !emails.is_empty()),
+        "next": (query.page + 1 < emails.len()).then_some(query.page + 1),
+    });
+
+    HBS.render("mailbox", &data)
+        .map(Html)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error.").into())
+}
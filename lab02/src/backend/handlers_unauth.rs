@@ -8,38 +8,119 @@ use axum::{
     response::{Html, IntoResponse, Redirect},
 };
 
-use crate::database::token::generate;
-use crate::database::user::{create, exists, set_passkey};
+use crate::{config, consts};
+use crate::database::token::{generate, Purpose};
+use crate::database::user::{create, exists, reset_credentials, Credential};
 use crate::database::{token, user};
-use crate::email::send_mail;
-use crate::utils::webauthn::{begin_authentication, begin_registration, complete_authentication, complete_registration, StoredRegistrationState, CREDENTIAL_STORE};
+use crate::email::{describe_duration, send_mail};
+use crate::utils::webauthn::{
+    begin_authentication, begin_discoverable_authentication, begin_registration,
+    complete_authentication, complete_discoverable_authentication, complete_registration,
+    StoredRegistrationState,
+};
 use crate::HBS;
-use log::debug;
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error};
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
+use std::time::{Duration as StdDuration, Instant};
 use tokio::sync::RwLock;
+use tower_sessions::Session;
 use validator::{ValidateEmail};
 use webauthn_rs::prelude::{
-    PasskeyAuthentication, PublicKeyCredential, RegisterPublicKeyCredential,
+    DiscoverableAuthentication, PasskeyAuthentication, PublicKeyCredential,
+    RegisterPublicKeyCredential, Uuid,
 };
 use crate::utils::input::is_valid_display_name;
 
-/// Structure pour gérer un état temporaire avec un challenge
-struct TimedStoredState<T> {
-    state: T,
-    server_challenge: String,
+/// État d'une authentification en cours
+enum PendingAuthentication {
+    /// Authentification d'un utilisateur identifié par son email
+    Passkey {
+        state: PasskeyAuthentication,
+        email: String,
+    },
+    /// Authentification sans nom d'utilisateur, le compte est déduit du user handle
+    Discoverable(DiscoverableAuthentication),
+}
+
+/// Cérémonie WebAuthn (enregistrement ou authentification) en cours, avec sa date de création
+pub(crate) struct Ceremony<T> {
+    pub state: T,
+    created_at: Instant,
+}
+
+impl<T> Ceremony<T> {
+    pub fn new(state: T) -> Self {
+        Ceremony {
+            state,
+            created_at: Instant::now(),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.created_at.elapsed() >= StdDuration::from_secs(consts::WEBAUTHN_STATE_TTL_SECS)
+    }
 }
 
 /// Stockage des états d'enregistrement et d'authentification
-pub(crate) static REGISTRATION_STATES: Lazy<RwLock<HashMap<String, StoredRegistrationState>>> =
+pub(crate) static REGISTRATION_STATES: Lazy<RwLock<HashMap<String, Ceremony<StoredRegistrationState>>>> =
+    Lazy::new(Default::default);
+static AUTHENTICATION_STATES: Lazy<RwLock<HashMap<String, Ceremony<PendingAuthentication>>>> =
     Lazy::new(Default::default);
-static AUTHENTICATION_STATES: Lazy<
-    RwLock<HashMap<String, TimedStoredState<PasskeyAuthentication>>>,
-> = Lazy::new(Default::default);
+
+/// Retire un état d'enregistrement, s'il existe et n'a pas expiré
+pub(crate) async fn take_registration_state(state_id: &str) -> Option<StoredRegistrationState> {
+    REGISTRATION_STATES
+        .write()
+        .await
+        .remove(state_id)
+        .filter(|ceremony| !ceremony.is_expired())
+        .map(|ceremony| ceremony.state)
+}
+
+/// Supprime les enregistrements et authentifications abandonnés et retourne leur nombre
+pub async fn purge_expired_states() -> usize {
+    let mut purged = 0;
+
+    let mut states = REGISTRATION_STATES.write().await;
+    let before = states.len();
+    states.retain(|_, ceremony| !ceremony.is_expired());
+    purged += before - states.len();
+    drop(states);
+
+    let mut states = AUTHENTICATION_STATES.write().await;
+    let before = states.len();
+    states.retain(|_, ceremony| !ceremony.is_expired());
+    purged + before - states.len()
+}
+
+/// Clé de session de l'autorisation de réinitialiser les passkeys d'un compte
+const RESET_SESSION_KEY: &str = "reset_grant";
+
+/// Autorisation de réinitialisation, accordée par un lien de récupération valide
+#[derive(Serialize, Deserialize)]
+struct ResetGrant {
+    email: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Vérifie que la session a consommé un lien de récupération pour ce compte
+fn reset_allowed(session: &Session, email: &str) -> bool {
+    matches!(
+        session.get::<ResetGrant>(RESET_SESSION_KEY),
+        Ok(Some(grant)) if grant.email == email && grant.expires_at > Utc::now()
+    )
+}
 
 /// Début du processus d'enregistrement WebAuthn
+///
+/// En mode récupération, la session doit avoir consommé un lien de récupération
+/// pour ce compte (voir [`reset_account`]).
 pub async fn register_begin(
+    session: Session,
     Json(payload): Json<serde_json::Value>,
 ) -> axum::response::Result<Json<serde_json::Value>> {
     let email = payload
@@ -56,29 +137,36 @@ pub async fn register_begin(
         .and_then(|v| v.as_bool())
         .unwrap_or(false);
 
+    if reset_mode && !reset_allowed(&session, email) {
+        return Err((StatusCode::FORBIDDEN, "Account recovery required").into());
+    }
+
     match (reset_mode, exists(email)) {
         (false, Ok(false)) => (),
         (true, Ok(true)) => (),
-        (true, Ok(false)) => (),
         (_, _) => return Err((StatusCode::BAD_REQUEST, "Invalid registration request").into()),
     }
 
-    let (public_key, pskr) = begin_registration(email, email)
+    // En mode récupération, le user handle existant est conservé
+    let user_handle = user::get(email)
+        .map(|u| u.user_handle)
+        .unwrap_or_else(Uuid::new_v4);
+
+    let (public_key, pskr) = begin_registration(user_handle, email, email, Vec::new())
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
 
     let stored_registration_state = StoredRegistrationState {
-        challenge: public_key["challenge"].as_str().unwrap().to_string(),
         registration_state: pskr,
+        email: email.to_string(),
+        user_handle,
     };
 
     let state_id = uuid::Uuid::new_v4().to_string();
     REGISTRATION_STATES
         .write()
         .await
-        .insert(state_id.clone(), stored_registration_state);
-
-    CREDENTIAL_STORE.write().await.remove(email);
+        .insert(state_id.clone(), Ceremony::new(stored_registration_state));
 
     Ok(Json(json!({
         "publicKey": public_key,
@@ -87,7 +175,11 @@ pub async fn register_begin(
 }
 
 /// Fin du processus d'enregistrement WebAuthn
+///
+/// En mode récupération, l'autorisation de la session est consommée une fois les
+/// passkeys remplacées.
 pub async fn register_complete(
+    session: Session,
     Json(payload): Json<serde_json::Value>,
 ) -> axum::response::Result<StatusCode> {
     let email = payload
@@ -104,6 +196,10 @@ pub async fn register_complete(
         .and_then(|v| v.as_bool())
         .unwrap_or(false);
 
+    if reset_mode && !reset_allowed(&session, email) {
+        return Err((StatusCode::FORBIDDEN, "Account recovery required").into());
+    }
+
     let first_name = payload
         .get("first_name")
         .and_then(|v| v.as_str())
@@ -134,20 +230,30 @@ pub async fn register_complete(
     )
     .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid response format"))?;
 
+    let stored_state = take_registration_state(state_id)
+        .await
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid registration session"))?;
+
+    // Rien n'est enregistré tant que l'attestation n'est pas vérifiée
+    let passkey = complete_registration(email, &response, &stored_state)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
     match (reset_mode, exists(email)) {
         (false, Ok(false)) => {
-            create(email, first_name, last_name)
+            let credential = Credential::new("Primary passkey", passkey);
+            let created = create(email, first_name, last_name, stored_state.user_handle, credential)
                 .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create user"))?;
+            if !created {
+                return Err((StatusCode::BAD_REQUEST, "Invalid registration request").into());
+            }
         }
         (true, Ok(true)) => {
-            // En vrai ici il faut modifer le userName et le LastName du user qui a recover
-            // Pas possible avec les outils fournit car la foncter get nous renvoi une copie
-            // du user. Toutefois, la passkey est bel est bien modifié. Le setPasskey est
-            // appelé dans la fonction `complete_registration(...)` juste en desous.
-            create(email, first_name, last_name)
-                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create user"))?;
-        },
-        (_, _) => {
+            reset_credentials(email, Credential::new("Recovered passkey", passkey))
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set passkey for user"))?;
+        }
+        (_, Ok(_)) => return Err((StatusCode::BAD_REQUEST, "Invalid registration request").into()),
+        (_, Err(_)) => {
             return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Failed to check user existence",
@@ -155,30 +261,26 @@ pub async fn register_complete(
         }
     }
 
-    let stored_state = {
-        let mut states = REGISTRATION_STATES.write().await;
-        states
-            .remove(state_id)
-            .ok_or((StatusCode::BAD_REQUEST, "Invalid registration session"))?
-    };
-
-    complete_registration(email, &response, &stored_state)
-        .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    if reset_mode {
+        session
+            .remove::<ResetGrant>(RESET_SESSION_KEY)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update session"))?;
+    }
 
-    if let Ok(verification_token) = generate(email) {
-        let verification_link = format!("http://localhost:8080/validate/{}", verification_token);
+    if let Ok(verification_token) = generate(email, Purpose::Verify) {
+        let verification_link = config::get().public_url(&format!("/validate/{}", verification_token));
+        let first_name = user::get(email).map(|u| u.first_name).unwrap_or_default();
 
         if let Err(err) = send_mail(
             email,
-            "Verifier votre compte",
-            &format!(
-                "Bienvenu! Veuillez verifier votre compte en clickant sur le lien: {}\n
-             Si vous n'etes pas à l'origine de l'action, ignorez cette email.",
-                verification_link
-            ),
+            "verify",
+            &json!({
+                "first_name": first_name,
+                "link": verification_link,
+                "expires_in": describe_duration(Purpose::Verify.ttl()),
+            }),
         ) {
-            eprintln!("Failed to send verification email to {}: {:?}", email, err);
+            error!("Failed to send verification email to {}: {:?}", email, err);
         }
     }
 
@@ -207,10 +309,10 @@ pub async fn login_begin(
 
     authentication_states.insert(
         state_id.clone(),
-        TimedStoredState {
+        Ceremony::new(PendingAuthentication::Passkey {
             state: pska,
-            server_challenge: public_key["challenge"].as_str().unwrap_or("").to_string(),
-        },
+            email: email.to_string(),
+        }),
     );
 
     Ok(Json(json!({
@@ -219,49 +321,85 @@ pub async fn login_begin(
     })))
 }
 
+/// Début du processus d'authentification WebAuthn sans nom d'utilisateur
+pub async fn login_discoverable_begin() -> axum::response::Result<Json<serde_json::Value>> {
+    let (public_key, dsa) = begin_discoverable_authentication()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let state_id = uuid::Uuid::new_v4().to_string();
+    AUTHENTICATION_STATES
+        .write()
+        .await
+        .insert(
+            state_id.clone(),
+            Ceremony::new(PendingAuthentication::Discoverable(dsa)),
+        );
+
+    Ok(Json(json!({
+        "publicKey": public_key,
+        "state_id": state_id,
+    })))
+}
+
 /// Fin du processus d'authentification WebAuthn
+///
+/// En cas de succès, l'identifiant de session est renouvelé (protection contre la
+/// fixation de session) et l'utilisateur authentifié y est enregistré.
 pub async fn login_complete(
+    session: Session,
     Json(payload): Json<serde_json::Value>,
 ) -> axum::response::Result<Redirect> {
     let response = payload
         .get("response")
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Response is required"))?;
+        .ok_or((StatusCode::BAD_REQUEST, "Response is required"))?;
     let state_id = payload
         .get("state_id")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "State ID is required"))?;
+        .ok_or((StatusCode::BAD_REQUEST, "State ID is required"))?;
 
     let credential: PublicKeyCredential = serde_json::from_value(response.clone())
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid response format"))?;
 
     let mut authentication_states = AUTHENTICATION_STATES.write().await;
 
-    let stored_state = authentication_states.remove(state_id).ok_or_else(|| {
-        (
+    let stored_state = authentication_states
+        .remove(state_id)
+        .filter(|state| !state.is_expired())
+        .ok_or((
             StatusCode::BAD_REQUEST,
             "Invalid or expired authentication state",
-        )
-    })?;
+        ))?;
 
-    complete_authentication(
-        &credential,
-        &stored_state.state,
-        &stored_state.server_challenge,
-    )
-    .await
+    let email = match stored_state.state {
+        PendingAuthentication::Passkey { state, email } => {
+            complete_authentication(&email, &credential, &state)
+                .await
+                .map(|_| email)
+        }
+        PendingAuthentication::Discoverable(state) => {
+            complete_discoverable_authentication(&credential, state).await
+        }
+    }
     .map_err(|e| (StatusCode::UNAUTHORIZED, e.to_string()))?;
 
+    session.cycle_id();
+    session
+        .insert("email", &email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session"))?;
+
     Ok(Redirect::to("/home"))
 }
 
-/// Gère la déconnexion de l'utilisateur
-pub async fn logout() -> impl IntoResponse {
+/// Gère la déconnexion de l'utilisateur en détruisant sa session
+pub async fn logout(session: Session) -> impl IntoResponse {
+    session.flush();
     Redirect::to("/")
 }
 
 /// Valide un compte utilisateur via un token
 pub async fn validate_account(Path(token): Path<String>) -> impl IntoResponse {
-    match token::consume(&token) {
+    match token::consume(&token, Purpose::Verify) {
         Ok(email) => match user::verify(&email) {
             Ok(_) => Redirect::to("/login?validated=true"),
             Err(_) => Redirect::to("/register?error=validation_failed"),
@@ -294,22 +432,26 @@ pub async fn recover_account(
     };
 
     if user_exists {
-        let token = generate(email).map_err(|_| {
+        let token = generate(email, Purpose::Recover).map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Failed to generate token",
             )
         })?;
 
-        let recovery_link = format!("http://localhost:8080/recover/{}", token);
-        let subject = "Récupération de compte";
-        let body = format!(
-            "Cliquez sur ce lien pour récupérer votre compte : {}",
-            recovery_link
-        );
+        let recovery_link = config::get().public_url(&format!("/recover/{}", token));
+        let first_name = user::get(email).map(|u| u.first_name).unwrap_or_default();
 
-        send_mail(email, subject, &body)
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send email"))?;
+        send_mail(
+            email,
+            "recover",
+            &json!({
+                "first_name": first_name,
+                "link": recovery_link,
+                "expires_in": describe_duration(Purpose::Recover.ttl()),
+            }),
+        )
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send email"))?;
 
         data.insert(
             "message",
@@ -323,8 +465,20 @@ pub async fn recover_account(
 }
 
 /// Gère la réinitialisation du compte utilisateur via un token de récupération
-pub async fn reset_account(Path(token): Path<String>) -> Html<String> {
-    match token::consume(&token) {
+///
+/// Le token consommé autorise la session, pour une courte durée, à remplacer les
+/// passkeys du compte.
+pub async fn reset_account(session: Session, Path(token): Path<String>) -> Html<String> {
+    let grant = token::consume(&token, Purpose::Recover).and_then(|email| {
+        let grant = ResetGrant {
+            email: email.clone(),
+            expires_at: Utc::now() + Duration::minutes(consts::RESET_GRANT_TTL_MINUTES),
+        };
+        session.insert(RESET_SESSION_KEY, grant)?;
+        Ok(email)
+    });
+
+    match grant {
         Ok(email) => {
             let redirect_url = format!("/register?reset_mode=true&email={}&success=true", email);
             Html(format!(
@@ -345,8 +499,8 @@ pub async fn reset_account(Path(token): Path<String>) -> Html<String> {
 /// --- Affichage des pages ---
 ///
 /// Affiche la page d'accueil
-pub async fn index(session: tower_sessions::Session) -> impl IntoResponse {
-    let is_logged_in = session.get::<String>("email").is_ok();
+pub async fn index(session: Session) -> impl IntoResponse {
+    let is_logged_in = matches!(session.get::<String>("email"), Ok(Some(_)));
     let mut data = HashMap::new();
     data.insert("logged_in", is_logged_in);
 
@@ -389,3 +543,124 @@ pub async fn register_page(Query(params): Query<HashMap<String, String>>) -> imp
 pub async fn recover_page() -> impl IntoResponse {
     Html(include_str!("../../templates/recover.hbs"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{mailed_token, unique_email, Authenticator, Client};
+
+    /// Demande la récupération du compte et suit le lien reçu par email
+    async fn follow_recovery_link(client: &mut Client, email: &str) {
+        let (status, _) = client.post_json("/recover", json!({ "email": email })).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let token = mailed_token(email, "/recover/").expect("recovery email not sent");
+        let (status, body) = client.get(&format!("/recover/{}", token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("reset_mode=true"), "recovery link refused: {}", body);
+    }
+
+    #[tokio::test]
+    async fn registered_user_can_log_in() {
+        let mut client = Client::new();
+        let mut authenticator = Authenticator::new();
+        let email = unique_email("login");
+
+        assert_eq!(client.register(&mut authenticator, &email, false).await, StatusCode::CREATED);
+        assert_eq!(client.get("/home").await.0, StatusCode::UNAUTHORIZED);
+
+        assert_eq!(client.login(&mut authenticator, &email).await, StatusCode::SEE_OTHER);
+        assert_eq!(client.get("/home").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn failed_registration_leaves_no_account() {
+        let mut client = Client::new();
+        let mut authenticator = Authenticator::new();
+        let email = unique_email("attestation");
+
+        // Réponse signée pour un autre défi que celui de l'état présenté
+        let begin = json!({ "email": email, "reset_mode": false });
+        let (_, first) = client.post_json("/register", begin.clone()).await;
+        let (_, second) = client.post_json("/register", begin).await;
+        let first: serde_json::Value = serde_json::from_str(&first).unwrap();
+        let second: serde_json::Value = serde_json::from_str(&second).unwrap();
+        let response = authenticator.register(&second["publicKey"]);
+
+        let (status, _) = client
+            .post_json(
+                "/register/complete",
+                json!({
+                    "email": email,
+                    "first_name": "Ada",
+                    "last_name": "Lovelace",
+                    "state_id": first["state_id"],
+                    "response": response,
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!exists(&email).unwrap());
+
+        assert_eq!(client.register(&mut authenticator, &email, false).await, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn login_replaces_session_id() {
+        let mut client = Client::new();
+        let mut authenticator = Authenticator::new();
+        let email = unique_email("cycle");
+        assert_eq!(client.register(&mut authenticator, &email, false).await, StatusCode::CREATED);
+
+        // Session ouverte avant la connexion (ici par le lien de récupération)
+        follow_recovery_link(&mut client, &email).await;
+        let before = client.cookie().expect("no session before login");
+
+        assert_eq!(client.login(&mut authenticator, &email).await, StatusCode::SEE_OTHER);
+        assert_ne!(client.cookie(), Some(before.clone()));
+        assert_eq!(client.get("/home").await.0, StatusCode::OK);
+
+        // L'ancien identifiant ne donne pas accès à la session connectée
+        client.set_cookie(Some(before));
+        assert_eq!(client.get("/home").await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn logout_invalidates_session() {
+        let mut client = Client::new();
+        let mut authenticator = Authenticator::new();
+        let email = unique_email("logout");
+        assert_eq!(client.register(&mut authenticator, &email, false).await, StatusCode::CREATED);
+        assert_eq!(client.login(&mut authenticator, &email).await, StatusCode::SEE_OTHER);
+        let session = client.cookie().expect("no session after login");
+
+        assert_eq!(client.get("/logout").await.0, StatusCode::SEE_OTHER);
+        assert_eq!(client.get("/home").await.0, StatusCode::UNAUTHORIZED);
+
+        // Le cookie rejoué ne correspond plus à aucune session
+        client.set_cookie(Some(session));
+        assert_eq!(client.get("/home").await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn reset_requires_recovery_grant() {
+        let mut client = Client::new();
+        let mut authenticator = Authenticator::new();
+        let email = unique_email("reset");
+        assert_eq!(client.register(&mut authenticator, &email, false).await, StatusCode::CREATED);
+
+        let mut replacement = Authenticator::new();
+        assert_eq!(client.register(&mut replacement, &email, true).await, StatusCode::FORBIDDEN);
+
+        follow_recovery_link(&mut client, &email).await;
+        assert_eq!(client.register(&mut replacement, &email, true).await, StatusCode::CREATED);
+
+        // La nouvelle passkey remplace l'ancienne
+        assert_eq!(user::get(&email).unwrap().credentials.len(), 1);
+        assert_eq!(client.login(&mut replacement, &email).await, StatusCode::SEE_OTHER);
+
+        // L'autorisation ne sert qu'une fois
+        let mut another = Authenticator::new();
+        assert_eq!(client.register(&mut another, &email, true).await, StatusCode::FORBIDDEN);
+    }
+}
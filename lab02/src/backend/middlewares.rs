@@ -6,7 +6,12 @@ use axum::http::{request::Parts, StatusCode};
 use tower_sessions::Session;
 
 /// Middleware pour valider une session utilisateur
-pub struct SessionUser;
+///
+/// Utilisé comme extracteur, il fournit l'identité de l'utilisateur authentifié.
+#[derive(Clone, Debug)]
+pub struct SessionUser {
+    pub email: String,
+}
 
 #[async_trait::async_trait]
 impl <S> FromRequestParts<S> for SessionUser
@@ -17,8 +22,8 @@ where
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
         if let Some(session) = parts.extensions.get::<Session>() {
-            if session.get::<String>("email").is_ok() {
-                return Ok(SessionUser);
+            if let Ok(Some(email)) = session.get::<String>("email") {
+                return Ok(SessionUser { email });
             }
         }
 
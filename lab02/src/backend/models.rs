@@ -1,11 +1,37 @@
 //! Définitions des structures pour les interactions avec l'API.
 //! Contient les structures pour l'enregistrement, l'authentification et la récupération.
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use crate::database::post::Sort;
 
 /// Structure pour représenter les réponses aux défis WebAuthn
 #[derive(Serialize)]
 pub struct WebAuthnChallenge {
+    #[serde(rename = "publicKey")]
     pub challenge: serde_json::Value, // Données du défi
     pub state_id: String,            // Identifiant d'état du défi
-}
\ No newline at end of file
+}
+
+/// Paramètres de pagination des commentaires d'un post
+#[derive(Deserialize)]
+pub struct CommentsQuery {
+    #[serde(default)]
+    pub offset: usize, // Nombre de commentaires de premier niveau à sauter
+}
+
+/// Paramètres de pagination du fil d'actualité
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    #[serde(default)]
+    pub sort: Sort,             // Ordre du fil
+    pub cursor: Option<String>, // Position après laquelle reprendre
+    pub tag: Option<String>,    // Hashtag pour filtrer le fil (sans `#`)
+}
+
+/// Paramètres d'une recherche dans les posts
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String, // Requête (mots, "phrase", préfixe*)
+    #[serde(default)]
+    pub offset: usize, // Nombre de résultats à sauter
+}
@@ -2,22 +2,31 @@
 //! Définit les routes accessibles avec ou sans authentification et configure les middlewares.
 
 use axum::{Router, routing::{get, post}, BoxError};
+use axum::extract::DefaultBodyLimit;
 use axum::error_handling::HandleErrorLayer;
 use http::StatusCode;
-use tower_sessions::{SessionManagerLayer, MemoryStore};
+use tower_sessions::{cookie::time::Duration, Expiry, SessionManagerLayer, MemoryStore};
 use tower_http::cors::{Any, CorsLayer};
 use tower::{ServiceBuilder};
+use crate::{config, consts};
 use crate::backend::handlers_unauth::{
-    register_begin, register_complete, login_begin, login_complete,
+    register_begin, register_complete, login_begin, login_discoverable_begin, login_complete,
     index, login_page, register_page, validate_account, logout,
     recover_page, recover_account, reset_account,
 };
-use crate::backend::handlers_auth::{create_post, home, like_post};
+use crate::backend::handlers_dev::mailbox;
+use crate::backend::handlers_auth::{
+    bookmark_post, bookmarks_page, comment_post, create_post, delete_post, edit_post, feed, home, like_post, mentions_page,
+    post_comments, search_posts, serve_media, tag_feed,
+    passkeys_page, passkey_add_begin, passkey_add_complete, passkey_rename, passkey_revoke,
+};
 
 /// Initialisation du routeur principal et des middlewares
 pub fn get_router() -> Router {
-    // Configuration CORS pour permettre les requêtes de n'importe quelle origine (en mode debug uniquement)
-    let router = if cfg!(debug_assertions) {
+    let features = &config::get().features;
+
+    // Configuration CORS pour permettre les requêtes de n'importe quelle origine (si activée)
+    let router = if features.cors {
         let cors = CorsLayer::new()
             .allow_methods(tower_http::cors::AllowMethods::any())
             .allow_origin(Any);
@@ -28,7 +37,9 @@ pub fn get_router() -> Router {
 
     // Configuration des sessions en mémoire
     let store = MemoryStore::default(); // Initialisation du MemoryStore
-    let session_manager = SessionManagerLayer::new(store).with_http_only(true);
+    let session_manager = SessionManagerLayer::new(store)
+        .with_http_only(true)
+        .with_expiry(Expiry::OnInactivity(Duration::minutes(consts::SESSION_INACTIVITY_MINUTES)));
 
     let service = ServiceBuilder::new()
         .layer(HandleErrorLayer::new(|_e: BoxError| async move {
@@ -36,12 +47,25 @@ pub fn get_router() -> Router {
         }))
         .layer(session_manager);
 
+    // Outils de développement (si activés)
+    let router = if features.dev_tools {
+        router.merge(dev_routes())
+    } else {
+        router
+    };
+
     router
         .merge(unauth_routes())
         .merge(auth_routes())
         .layer(service)
 }
 
+/// Routes de développement
+fn dev_routes() -> Router {
+    Router::new()
+        .route("/dev/mailbox", get(mailbox)) // Emails enregistrés, avec liens cliquables
+}
+
 /// Routes accessibles sans authentification
 fn unauth_routes() -> Router {
     Router::new()
@@ -50,6 +74,7 @@ fn unauth_routes() -> Router {
         .route("/register", get(register_page).post(register_begin)) // Début de l'enregistrement WebAuthn
         .route("/register/complete", post(register_complete)) // Fin de l'enregistrement WebAuthn
         .route("/login", get(login_page).post(login_begin)) // Page de connexion
+        .route("/login/discoverable", post(login_discoverable_begin)) // Début de l'authentification sans nom d'utilisateur
         .route("/login/complete", post(login_complete)) // Fin de l'authentification WebAuthn
         .route("/logout", get(logout)) // Déconnexion
         .route("/recover", get(recover_page).post(recover_account)) // Page et handler de récupération
@@ -60,7 +85,26 @@ fn unauth_routes() -> Router {
 fn auth_routes() -> Router {
     Router::new()
         .route("/home", get(home)) // Page principale
+        .route("/feed", get(feed)) // Fil d'actualité en JSON, par page
+        .route("/search", get(search_posts)) // Recherche plein texte dans les posts
+        .route("/tag/:tag", get(tag_feed)) // Posts portant un hashtag
+        .route("/mentions", get(mentions_page)) // Posts mentionnant l'utilisateur connecté
+        .route("/bookmarks", get(bookmarks_page)) // Posts enregistrés
+        .route("/post/bookmark", post(bookmark_post)) // Enregistrement ou retrait d'un post des favoris
         .route("/post/like", post(like_post)) // Ajout d'un like à un post
-        .route("/post/create", post(create_post)) // Ajout d'un post
+        .route(
+            "/post/create",
+            post(create_post).layer(DefaultBodyLimit::max(consts::MAX_UPLOAD_BODY_BYTES)),
+        ) // Ajout d'un post, l'image pouvant dépasser la limite par défaut d'axum
+        .route("/post/edit", post(edit_post)) // Modification d'un post
+        .route("/post/delete", post(delete_post)) // Suppression d'un post
+        .route("/post/comment", post(comment_post)) // Ajout d'un commentaire ou d'une réponse
+        .route("/post/:id/comments", get(post_comments)) // Commentaires d'un post, par page
+        .route("/media/:id", get(serve_media)) // Images des posts
+        .route("/passkeys", get(passkeys_page)) // Gestion des passkeys
+        .route("/passkeys/add", post(passkey_add_begin)) // Début de l'ajout d'une passkey
+        .route("/passkeys/add/complete", post(passkey_add_complete)) // Fin de l'ajout d'une passkey
+        .route("/passkeys/rename", post(passkey_rename)) // Renommage d'une passkey
+        .route("/passkeys/revoke", post(passkey_revoke)) // Révocation d'une passkey
         .layer(axum::middleware::from_extractor::<crate::backend::middlewares::SessionUser>()) // Middleware pour vérifier l'utilisateur connecté
 }
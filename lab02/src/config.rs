@@ -0,0 +1,428 @@
+//! Configuration de l'application.
+//! Lue au démarrage depuis un fichier TOML optionnel, puis depuis les variables d'environnement
+//! (y compris celles de `.env`), qui ont priorité. Toutes les valeurs sont validées avant le
+//! démarrage du serveur; les erreurs sont toutes rapportées en une fois.
+//!
+//! Exemple de `config.toml`:
+//!
+//! ```toml
+//! bind_address = "0.0.0.0:8080"
+//! public_origin = "https://lab02.example.com"
+//! allowed_origins = ["https://www.lab02.example.com"]
+//!
+//! [webauthn]
+//! rp_id = "lab02.example.com"
+//! rp_name = "SLH Lab02"
+//!
+//! [storage]
+//! backend = "sqlite"
+//! data_dir = "/var/lib/lab02"
+//!
+//! [mail]
+//! transport = "smtp"
+//! smtp_host = "smtp.example.com"
+//! smtp_port = 587
+//! smtp_from = "Lab02 <noreply@example.com>"
+//!
+//! [features]
+//! dev_tools = false
+//! cors = false
+//! ```
+
+use std::{
+    fs::read_to_string,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use url::Url;
+use crate::consts;
+use crate::database::storage::Backend;
+use crate::email::transport::{SmtpTls, TransportKind};
+
+/// Configuration active, chargée une seule fois au démarrage
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Configuration validée de l'application
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Adresse d'écoute du serveur HTTP
+    pub bind_address: SocketAddr,
+    /// Origine publique du site, utilisée pour les liens des emails et par WebAuthn
+    pub public_origin: Url,
+    /// Origines supplémentaires acceptées par WebAuthn
+    pub allowed_origins: Vec<Url>,
+    /// Identifiant de la relying party WebAuthn (domaine de l'origine publique ou un de ses parents)
+    pub rp_id: String,
+    /// Nom de la relying party affiché par les authentificateurs
+    pub rp_name: String,
+    /// Dossier des images uploadées
+    pub uploads_dir: PathBuf,
+    /// Backend de stockage, avec le dossier ou le fichier des données
+    pub storage: Backend,
+    /// Transport des emails
+    pub mail: TransportKind,
+    pub features: Features,
+}
+
+/// Fonctionnalités activables
+#[derive(Clone, Debug)]
+pub struct Features {
+    /// Outils de développement (`/dev/mailbox`)
+    pub dev_tools: bool,
+    /// CORS permissif, pour le développement du frontend
+    pub cors: bool,
+}
+
+/// Contenu du fichier TOML. Tous les champs sont optionnels.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    bind_address: Option<String>,
+    public_origin: Option<String>,
+    allowed_origins: Option<Vec<String>>,
+    #[serde(default)]
+    webauthn: WebauthnSection,
+    #[serde(default)]
+    storage: StorageSection,
+    #[serde(default)]
+    mail: MailSection,
+    #[serde(default)]
+    features: FeaturesSection,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WebauthnSection {
+    rp_id: Option<String>,
+    rp_name: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StorageSection {
+    backend: Option<String>,
+    data_dir: Option<String>,
+    uploads_dir: Option<String>,
+    sqlite_path: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MailSection {
+    transport: Option<String>,
+    smtp_host: Option<String>,
+    smtp_port: Option<u16>,
+    smtp_tls: Option<String>,
+    smtp_username: Option<String>,
+    smtp_password: Option<String>,
+    smtp_from: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeaturesSection {
+    dev_tools: Option<bool>,
+    cors: Option<bool>,
+}
+
+/// Valeur d'une variable d'environnement, si elle est définie et non vide
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+/// Valeur d'une variable d'environnement, sinon du fichier de configuration
+fn setting(name: &str, file: Option<String>) -> Option<String> {
+    env(name).or(file)
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(format!("{}: expected true or false, got {:?}", name, value)),
+    }
+}
+
+/// Une origine est un schéma http(s) et un nom de domaine, sans chemin.
+/// WebAuthn n'accepte pas les adresses IP.
+fn parse_origin(name: &str, value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| format!("{}: invalid URL {:?}: {}", name, value, e))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("{}: {:?} must use http or https", name, value));
+    }
+    if url.host_str().is_none() {
+        return Err(format!("{}: {:?} has no host", name, value));
+    }
+    if url.domain().is_none() {
+        return Err(format!("{}: {:?} must use a domain name, not an IP address (e.g. localhost)", name, value));
+    }
+    if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+        return Err(format!("{}: {:?} must not contain a path, query or fragment", name, value));
+    }
+    Ok(url)
+}
+
+/// Le RP ID doit être le domaine de l'origine ou un de ses parents
+fn check_rp_id(rp_id: &str, origin: &Url) -> Result<(), String> {
+    let Some(host) = origin.domain() else {
+        return Err(format!("RP_ID: the origin {} has no domain name", origin));
+    };
+    if rp_id.is_empty() {
+        return Err("RP_ID: must not be empty".to_string());
+    }
+    if host == rp_id || host.ends_with(&format!(".{}", rp_id)) {
+        Ok(())
+    } else {
+        Err(format!("RP_ID: {:?} is not a registrable suffix of the origin {}", rp_id, origin))
+    }
+}
+
+impl Config {
+    /// Charge et valide la configuration
+    pub fn load() -> Result<Self> {
+        let file = match env("CONFIG_FILE") {
+            Some(path) => read_file(Path::new(&path))?,
+            None if Path::new(consts::DEFAULT_CONFIG_FILE).exists() => read_file(Path::new(consts::DEFAULT_CONFIG_FILE))?,
+            None => FileConfig::default(),
+        };
+
+        let mut errors = Vec::new();
+
+        let bind_address = setting("BIND_ADDRESS", file.bind_address)
+            .unwrap_or_else(|| consts::DEFAULT_BIND_ADDRESS.to_string());
+        let bind_address = bind_address
+            .parse()
+            .map_err(|_| errors.push(format!("BIND_ADDRESS: {:?} is not a valid socket address (e.g. 0.0.0.0:8080)", bind_address)))
+            .ok();
+
+        let public_origin = setting("PUBLIC_ORIGIN", file.public_origin)
+            .unwrap_or_else(|| consts::DEFAULT_PUBLIC_ORIGIN.to_string());
+        let public_origin = parse_origin("PUBLIC_ORIGIN", &public_origin).map_err(|e| errors.push(e)).ok();
+
+        let allowed_origins: Vec<Url> = match env("ALLOWED_ORIGINS") {
+            Some(origins) => origins.split(',').map(|o| o.trim().to_string()).collect(),
+            None => file.allowed_origins.unwrap_or_default(),
+        }
+        .iter()
+        .filter_map(|origin| parse_origin("ALLOWED_ORIGINS", origin).map_err(|e| errors.push(e)).ok())
+        .collect();
+
+        let rp_id = setting("RP_ID", file.webauthn.rp_id)
+            .or_else(|| public_origin.as_ref().and_then(|o| o.domain()).map(|d| d.to_string()))
+            .unwrap_or_default();
+        for origin in public_origin.iter().chain(allowed_origins.iter()) {
+            if let Err(e) = check_rp_id(&rp_id, origin) {
+                errors.push(e);
+            }
+        }
+
+        let rp_name = setting("RP_NAME", file.webauthn.rp_name).unwrap_or_else(|| consts::DEFAULT_RP_NAME.to_string());
+        if rp_name.trim().is_empty() {
+            errors.push("RP_NAME: must not be empty".to_string());
+        }
+
+        // Données
+        let data_dir = PathBuf::from(
+            setting("DATA_DIR", file.storage.data_dir).unwrap_or_else(|| consts::DEFAULT_DATA_DIR.to_string()),
+        );
+        let uploads_dir = setting("UPLOADS_DIR", file.storage.uploads_dir)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| data_dir.join(consts::UPLOADS_DIR_NAME));
+        for (name, dir) in [("DATA_DIR", &data_dir), ("UPLOADS_DIR", &uploads_dir)] {
+            if dir.exists() && !dir.is_dir() {
+                errors.push(format!("{}: {} is not a directory", name, dir.display()));
+            }
+        }
+
+        let storage = match setting("STORAGE_BACKEND", file.storage.backend).as_deref() {
+            None | Some("yaml") => Some(Backend::Yaml { data_dir: data_dir.clone() }),
+            Some("sqlite") => Some(Backend::Sqlite {
+                path: setting("SQLITE_PATH", file.storage.sqlite_path)
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| data_dir.join(consts::SQLITE_DB_FILE)),
+            }),
+            Some(other) => {
+                errors.push(format!("STORAGE_BACKEND: unknown backend {:?} (expected yaml or sqlite)", other));
+                None
+            }
+        };
+
+        let mail = mail_transport(file.mail).map_err(|e| errors.extend(e)).ok();
+
+        // Fonctionnalités: actives par défaut en mode debug uniquement
+        let mut feature = |name: &str, file: Option<bool>| match env(name) {
+            Some(value) => parse_bool(name, &value).map_err(|e| errors.push(e)).unwrap_or(false),
+            None => file.unwrap_or(cfg!(debug_assertions)),
+        };
+        let features = Features {
+            dev_tools: feature("DEV_TOOLS", file.features.dev_tools),
+            cors: feature("CORS", file.features.cors),
+        };
+
+        match (bind_address, public_origin, storage, mail) {
+            (Some(bind_address), Some(public_origin), Some(storage), Some(mail)) if errors.is_empty() => Ok(Config {
+                bind_address,
+                public_origin,
+                allowed_origins,
+                rp_id,
+                rp_name,
+                uploads_dir,
+                storage,
+                mail,
+                features,
+            }),
+            _ => Err(anyhow!("Invalid configuration:\n  - {}", errors.join("\n  - "))),
+        }
+    }
+
+    /// Lien absolu vers une page du site, par exemple pour les emails
+    pub fn public_url(&self, path: &str) -> String {
+        self.public_origin
+            .join(path)
+            .map(String::from)
+            .unwrap_or_else(|_| format!("{}{}", self.public_origin.as_str().trim_end_matches('/'), path))
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig> {
+    let content = read_to_string(path).with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Invalid configuration file {}", path.display()))
+}
+
+/// Transport des emails (`MAIL_TRANSPORT` et `SMTP_*`)
+fn mail_transport(file: MailSection) -> Result<TransportKind, Vec<String>> {
+    match setting("MAIL_TRANSPORT", file.transport).as_deref() {
+        None | Some("file") => Ok(TransportKind::File),
+        Some("log") => Ok(TransportKind::Log),
+        Some("smtp") => {
+            let mut errors = Vec::new();
+
+            let tls = match setting("SMTP_TLS", file.smtp_tls).as_deref() {
+                None | Some("starttls") => SmtpTls::StartTls,
+                Some("none") => SmtpTls::None,
+                Some(other) => {
+                    errors.push(format!("SMTP_TLS: unknown mode {:?} (expected starttls or none)", other));
+                    SmtpTls::StartTls
+                }
+            };
+            // Seule la variable d'environnement est une chaîne à convertir
+            let port = match env("SMTP_PORT") {
+                Some(port) => port
+                    .parse()
+                    .map_err(|_| errors.push(format!("SMTP_PORT: {:?} is not a valid port", port)))
+                    .ok(),
+                None => file.smtp_port,
+            };
+            let port = match port {
+                Some(port) => port,
+                None => match tls {
+                    SmtpTls::StartTls => 587,
+                    SmtpTls::None => 25,
+                },
+            };
+            let credentials = match (
+                setting("SMTP_USERNAME", file.smtp_username),
+                setting("SMTP_PASSWORD", file.smtp_password),
+            ) {
+                (Some(username), Some(password)) => Some((username, password)),
+                (None, None) => None,
+                _ => {
+                    errors.push("SMTP_USERNAME and SMTP_PASSWORD must be set together".to_string());
+                    None
+                }
+            };
+            let host = setting("SMTP_HOST", file.smtp_host);
+            if host.is_none() {
+                errors.push("SMTP_HOST: required when MAIL_TRANSPORT is smtp".to_string());
+            }
+            let from = setting("SMTP_FROM", file.smtp_from);
+            match &from {
+                None => errors.push("SMTP_FROM: required when MAIL_TRANSPORT is smtp".to_string()),
+                Some(from) if from.parse::<lettre::message::Mailbox>().is_err() => {
+                    errors.push(format!("SMTP_FROM: {:?} is not a valid address", from))
+                }
+                Some(_) => (),
+            }
+
+            match (host, from) {
+                (Some(host), Some(from)) if errors.is_empty() => Ok(TransportKind::Smtp { host, port, tls, credentials, from }),
+                _ => Err(errors),
+            }
+        }
+        Some(other) => Err(vec![format!("MAIL_TRANSPORT: unknown transport {:?} (expected file, log or smtp)", other)]),
+    }
+}
+
+/// Enregistre la configuration chargée au démarrage
+pub fn init(config: Config) -> Result<()> {
+    CONFIG.set(config).map_err(|_| anyhow!("Configuration already loaded"))
+}
+
+/// Configuration active. Doit être appelée après [`init`].
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("Configuration not loaded")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin(value: &str) -> Url {
+        parse_origin("PUBLIC_ORIGIN", value).unwrap()
+    }
+
+    #[test]
+    fn parse_origin_accepts_domains() {
+        assert_eq!(origin("http://localhost:8080").domain(), Some("localhost"));
+        assert_eq!(origin("https://lab02.example.com").domain(), Some("lab02.example.com"));
+        assert_eq!(origin("https://lab02.example.com/").as_str(), "https://lab02.example.com/");
+    }
+
+    #[test]
+    fn parse_origin_rejects_ip_addresses() {
+        for value in ["http://127.0.0.1:8080", "https://[::1]:8443"] {
+            let error = parse_origin("PUBLIC_ORIGIN", value).unwrap_err();
+            assert!(error.contains("IP address"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn parse_origin_rejects_invalid_origins() {
+        for value in [
+            "localhost:8080",
+            "ftp://example.com",
+            "https://example.com/app",
+            "https://example.com/?q=1",
+            "https://example.com/#top",
+            "not a url",
+        ] {
+            assert!(parse_origin("PUBLIC_ORIGIN", value).is_err(), "{:?} should be rejected", value);
+        }
+    }
+
+    #[test]
+    fn check_rp_id_accepts_origin_domain_and_parents() {
+        let origin = origin("https://lab02.example.com");
+        assert!(check_rp_id("lab02.example.com", &origin).is_ok());
+        assert!(check_rp_id("example.com", &origin).is_ok());
+    }
+
+    #[test]
+    fn check_rp_id_rejects_unrelated_domains() {
+        let origin = origin("https://lab02.example.com");
+        for rp_id in ["", "other.com", "ample.com", "www.lab02.example.com", "b02.example.com"] {
+            assert!(check_rp_id(rp_id, &origin).is_err(), "{:?} should be rejected", rp_id);
+        }
+    }
+
+    #[test]
+    fn smtp_port_is_an_integer_in_toml() {
+        let file: FileConfig = toml::from_str("[mail]\nsmtp_port = 1025\n").unwrap();
+        assert_eq!(file.mail.smtp_port, Some(1025));
+        assert!(toml::from_str::<FileConfig>("[mail]\nsmtp_port = \"1025\"\n").is_err());
+    }
+}
@@ -1,8 +1,39 @@
 //! Définition des constantes globales pour l'application.
 
-pub const HTTP_PORT: u16 = 8080; // Port par défaut pour le serveur HTTP.
-pub const USERS_DB_PATH: &str = "./data/users.yaml"; // Chemin de la base de données des utilisateurs.
-pub const EMAILS_DB_PATH: &str = "./data/emails.yaml"; // Chemin de la base de données des emails.
-pub const POSTS_DB_PATH: &str = "./data/posts.yaml"; // Chemin de la base de données des posts.
-pub const UPLOADS_DIR: &str = "./data/uploads"; // Dossier pour les fichiers uploadés.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml"; // Fichier de configuration lu s'il existe.
+pub const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:8080"; // Adresse d'écoute par défaut du serveur HTTP.
+pub const DEFAULT_PUBLIC_ORIGIN: &str = "http://localhost:8080"; // Origine publique par défaut du site.
+pub const DEFAULT_RP_NAME: &str = "SLH Lab02"; // Nom par défaut de la relying party WebAuthn.
+pub const DEFAULT_DATA_DIR: &str = "./data"; // Dossier par défaut des données.
+pub const USERS_DB_FILE: &str = "users.yaml"; // Fichier de la base de données des utilisateurs.
+pub const EMAILS_DB_FILE: &str = "emails.yaml"; // Fichier de la base de données des emails.
+pub const TOKENS_DB_FILE: &str = "tokens.yaml"; // Fichier de la base de données des tokens.
+pub const POSTS_DB_FILE: &str = "posts.yaml"; // Fichier de la base de données des posts.
+pub const COMMENTS_DB_FILE: &str = "comments.yaml"; // Fichier de la base de données des commentaires.
+pub const YAML_BACKUP_COUNT: usize = 3; // Nombre de sauvegardes conservées pour chaque fichier YAML.
+pub const SQLITE_DB_FILE: &str = "lab02.sqlite3"; // Fichier par défaut de la base de données SQLite.
+pub const UPLOADS_DIR_NAME: &str = "uploads"; // Dossier par défaut des fichiers uploadés, dans le dossier des données.
+pub const FEED_PAGE_SIZE: usize = 20; // Nombre de posts par page du fil.
+pub const SEARCH_PAGE_SIZE: usize = 20; // Nombre de résultats par page de recherche.
+pub const MAX_COMMENT_LENGTH: usize = 500; // Longueur maximale d'un commentaire.
+pub const COMMENTS_PAGE_SIZE: usize = 20; // Nombre de commentaires par page.
+pub const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024; // Taille maximale d'une image uploadée.
+pub const MAX_UPLOAD_BODY_BYTES: usize = MAX_UPLOAD_BYTES + 64 * 1024; // Taille maximale d'une requête de création de post (image et champs du formulaire).
+pub const MAX_IMAGE_DIMENSION: u32 = 500; // Les images plus grandes sont réduites à cette taille.
+pub const THUMBNAIL_DIMENSION: u32 = 150; // Taille des miniatures affichées dans le fil.
+pub const MAX_DECODED_DIMENSION: u32 = 8192; // Dimension maximale acceptée au décodage.
+pub const MEDIA_GC_INTERVAL_SECS: u64 = 60 * 60; // Intervalle de suppression des médias orphelins.
+pub const MEDIA_GC_GRACE_SECS: u64 = 10 * 60; // Âge minimal d'un média avant de pouvoir être supprimé.
+pub const VERIFY_TOKEN_TTL_MINUTES: i64 = 24 * 60; // Durée de validité d'un lien de vérification.
+pub const RECOVER_TOKEN_TTL_MINUTES: i64 = 30; // Durée de validité d'un lien de récupération.
+pub const RESET_GRANT_TTL_MINUTES: i64 = 15; // Durée pour remplacer ses passkeys après un lien de récupération.
+pub const WEBAUTHN_STATE_TTL_SECS: u64 = 10 * 60; // Durée de validité d'un défi d'enregistrement ou de connexion.
+pub const WEBAUTHN_STATE_SWEEP_INTERVAL_SECS: u64 = 60; // Intervalle de nettoyage des défis abandonnés.
+pub const TOKEN_SWEEP_INTERVAL_SECS: u64 = 5 * 60; // Intervalle de nettoyage des tokens expirés.
+pub const OUTBOX_POLL_INTERVAL_SECS: u64 = 30; // Intervalle de relève de la file d'emails.
+pub const OUTBOX_BATCH_SIZE: usize = 20; // Nombre d'emails envoyés par relève.
+pub const OUTBOX_MAX_ATTEMPTS: u32 = 6; // Nombre d'essais avant d'abandonner un email.
+pub const OUTBOX_RETRY_BASE_SECS: i64 = 30; // Délai avant le premier nouvel essai, doublé à chaque échec.
+pub const OUTBOX_RETRY_MAX_SECS: i64 = 60 * 60; // Délai maximal entre deux essais.
+pub const SESSION_INACTIVITY_MINUTES: i64 = 60; // Durée d'inactivité avant l'expiration d'une session.
 
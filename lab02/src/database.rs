@@ -1,207 +1,1112 @@
-//! Gestion des bases de données pour les utilisateurs, tokens, et emails.
-
-use std::{
-    collections::HashMap,
-    fs::{create_dir_all, File},
-    path::Path,
-    sync::RwLock,
-};
+//! Gestion des bases de données pour les utilisateurs, tokens, emails et posts.
+//! Les données sont persistées par le backend de stockage choisi au démarrage (voir [`storage`]).
+
+mod search;
+mod sqlite;
+pub mod storage;
+mod yaml;
+
+use std::sync::RwLock;
 use anyhow::{anyhow, Result};
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
-use serde_yaml::{self, to_writer};
 use crate::consts;
+use self::storage::{Backend, Storage};
+
+/// Backend de stockage actif, initialisé une seule fois au démarrage
+static STORAGE: OnceCell<Box<dyn Storage>> = OnceCell::new();
+
+/// Index de recherche des posts, construit au démarrage
+static SEARCH: Lazy<RwLock<search::SearchIndex>> = Lazy::new(Default::default);
+
+/// Ouvre le backend de stockage choisi et prépare les données
+pub fn init(backend: Backend) -> Result<()> {
+    let storage: Box<dyn Storage> = match backend {
+        Backend::Yaml { data_dir } => Box::new(yaml::YamlStorage::open(&data_dir)?),
+        Backend::Sqlite { path } => Box::new(sqlite::SqliteStorage::open(&path)?),
+    };
+
+    STORAGE
+        .set(storage)
+        .map_err(|_| anyhow!("Storage already initialized"))?;
+
+    user::migrate_legacy_users()?;
+    post::build_search_index()
+}
+
+fn storage() -> Result<&'static dyn Storage> {
+    STORAGE
+        .get()
+        .map(|s| s.as_ref())
+        .ok_or_else(|| anyhow!("Storage not initialized"))
+}
+
+/// Stockage YAML partagé par les tests, dans un dossier temporaire propre au processus
+#[cfg(test)]
+pub(crate) fn init_for_tests() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let data_dir = std::env::temp_dir().join(format!("lab02-db-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        init(Backend::Yaml { data_dir }).expect("Failed to open test storage");
+    });
+}
+
+fn search_index() -> Result<std::sync::RwLockWriteGuard<'static, search::SearchIndex>> {
+    SEARCH.write().or(Err(anyhow!("Search index poisoned")))
+}
 
 // Gestion des utilisateurs
 pub mod user {
     use super::*;
-    use once_cell::sync::Lazy;
-    use webauthn_rs::prelude::Passkey;
+    use std::collections::HashMap;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+    use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+    use super::post::Reaction;
+
+    /// Passkey nommée enregistrée pour un utilisateur
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Credential {
+        pub name: String,
+        pub passkey: Passkey,
+        pub created_at: DateTime<Utc>,
+        pub last_used_at: Option<DateTime<Utc>>,
+    }
+
+    impl Credential {
+        pub fn new(name: &str, passkey: Passkey) -> Self {
+            Credential {
+                name: name.to_string(),
+                passkey,
+                created_at: Utc::now(),
+                last_used_at: None,
+            }
+        }
+
+        /// Identifiant de la passkey (credential ID encodé en base64url)
+        pub fn id(&self) -> String {
+            URL_SAFE_NO_PAD.encode(self.passkey.cred_id())
+        }
+    }
 
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct User {
         pub first_name: String,
         pub last_name: String,
         pub email: String,
+        /// Identifiant WebAuthn opaque et stable, généré une seule fois à la création du compte
+        #[serde(default)]
+        pub user_handle: Uuid,
+        #[serde(default)]
+        pub credentials: Vec<Credential>,
+        /// Passkey unique des comptes créés avant les passkeys multiples, migrée dans
+        /// `credentials` au démarrage (voir [`migrate_legacy_users`])
+        #[serde(default, skip_serializing)]
         pub passkey: Option<Passkey>,
         pub verified: bool,
+        /// Posts enregistrés (identifiants), dans l'ordre d'enregistrement
+        #[serde(default)]
         pub stash: Vec<String>,
-        pub liked_posts: Vec<u64>,
+        /// Réaction de l'utilisateur pour chaque post
+        #[serde(default)]
+        pub reactions: HashMap<Uuid, Reaction>,
     }
 
-    type Db = HashMap<String, User>;
-    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    /// Crée un compte avec sa première passkey. Retourne `false` si l'email est déjà utilisé.
+    pub fn create(
+        email: &str,
+        first_name: &str,
+        last_name: &str,
+        user_handle: Uuid,
+        credential: Credential,
+    ) -> Result<bool> {
+        if user_handle.is_nil() {
+            return Err(anyhow!("Invalid user handle"));
+        }
 
-    pub fn create(email: &str, first_name: &str, last_name: &str) -> Result<bool> {
         let user = User {
             first_name: first_name.to_string(),
             last_name: last_name.to_string(),
             email: email.to_string(),
+            user_handle,
+            credentials: vec![credential],
             passkey: None,
             verified: false,
             stash: Vec::new(),
-            liked_posts: Vec::new(),
+            reactions: HashMap::new(),
         };
 
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        storage()?.insert_user(user)
+    }
 
-        if db.contains_key(email) {
-            return Ok(false);
+    /// Applique une modification à un utilisateur existant
+    fn update(email: &str, f: &mut dyn FnMut(&mut User) -> Result<()>) -> Result<()> {
+        if !storage()?.update_user(email, f)? {
+            return Err(anyhow!("User not found"));
         }
+        Ok(())
+    }
 
-        db.insert(email.to_string(), user);
-        save(&db)?;
-        Ok(true)
+    /// Ajoute une passkey aux passkeys existantes de l'utilisateur
+    pub fn add_credential(email: &str, credential: Credential) -> Result<()> {
+        update(email, &mut |user| {
+            if user.credentials.iter().any(|c| c.passkey.cred_id() == credential.passkey.cred_id()) {
+                return Err(anyhow!("Passkey already registered"));
+            }
+            user.credentials.push(credential.clone());
+            Ok(())
+        })
     }
 
-    pub fn set_passkey(email: &str, passkey: Passkey) -> Result<()> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
-        user.passkey = Some(passkey);
-        save(&db)?;
-        Ok(())
+    /// Remplace toutes les passkeys de l'utilisateur (récupération de compte)
+    pub fn reset_credentials(email: &str, credential: Credential) -> Result<()> {
+        update(email, &mut |user| {
+            user.credentials = vec![credential.clone()];
+            Ok(())
+        })
     }
 
-    pub fn get_passkey(email: &str) -> Result<Option<Passkey>> {
-        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
-        let user = db.get(email).ok_or_else(|| anyhow!("User not found"))?;
-        Ok(user.passkey.clone())
+    pub fn get_credentials(email: &str) -> Result<Vec<Credential>> {
+        let user = storage()?.get_user(email)?.ok_or_else(|| anyhow!("User not found"))?;
+        Ok(user.credentials)
+    }
+
+    pub fn rename_credential(email: &str, credential_id: &str, name: &str) -> Result<()> {
+        update(email, &mut |user| {
+            let credential = user
+                .credentials
+                .iter_mut()
+                .find(|c| c.id() == credential_id)
+                .ok_or_else(|| anyhow!("Passkey not found"))?;
+            credential.name = name.to_string();
+            Ok(())
+        })
+    }
+
+    /// Révoque une passkey. La dernière passkey d'un compte ne peut pas être révoquée.
+    pub fn revoke_credential(email: &str, credential_id: &str) -> Result<()> {
+        update(email, &mut |user| {
+            let index = user
+                .credentials
+                .iter()
+                .position(|c| c.id() == credential_id)
+                .ok_or_else(|| anyhow!("Passkey not found"))?;
+            if user.credentials.len() == 1 {
+                return Err(anyhow!("Cannot revoke the last passkey"));
+            }
+            user.credentials.remove(index);
+            Ok(())
+        })
+    }
+
+    /// Enregistre un post dans les favoris de l'utilisateur, ou l'en retire s'il y est déjà.
+    /// Retourne `true` si le post est maintenant enregistré.
+    pub fn toggle_bookmark(email: &str, post_id: Uuid) -> Result<bool> {
+        let id = post_id.to_string();
+        let mut bookmarked = false;
+        update(email, &mut |user| {
+            bookmarked = match user.stash.iter().position(|saved| *saved == id) {
+                Some(index) => {
+                    user.stash.remove(index);
+                    false
+                }
+                None => {
+                    user.stash.push(id.clone());
+                    true
+                }
+            };
+            Ok(())
+        })?;
+        Ok(bookmarked)
+    }
+
+    /// Oublie un post supprimé: favoris et réaction. Retourne `true` si l'utilisateur a changé.
+    pub(super) fn forget_post(user: &mut User, post_id: Uuid) -> bool {
+        let id = post_id.to_string();
+        let before = user.stash.len();
+        user.stash.retain(|saved| *saved != id);
+        let reacted = user.reactions.remove(&post_id).is_some();
+        reacted || user.stash.len() != before
+    }
+
+    /// Met à jour la passkey utilisée après une authentification réussie
+    pub fn record_credential_use(email: &str, result: &AuthenticationResult) -> Result<()> {
+        update(email, &mut |user| {
+            let credential = user
+                .credentials
+                .iter_mut()
+                .find(|c| c.passkey.cred_id() == result.cred_id())
+                .ok_or_else(|| anyhow!("Passkey not found"))?;
+            credential.passkey.update_credential(result);
+            credential.last_used_at = Some(Utc::now());
+            Ok(())
+        })
     }
 
     pub fn get(email: &str) -> Option<User> {
-        DB.read().ok()?.get(email).cloned()
+        storage().ok()?.get_user(email).ok()?
+    }
+
+    /// Recherche un utilisateur par son identifiant WebAuthn (user handle)
+    pub fn find_by_handle(user_handle: Uuid) -> Option<User> {
+        storage().ok()?.find_user_by_handle(user_handle).ok()?
+    }
+
+    /// Recherche l'utilisateur possédant une passkey, en parcourant tous les comptes
+    pub fn find_by_credential(credential_id: &[u8]) -> Option<User> {
+        storage()
+            .ok()?
+            .users()
+            .ok()?
+            .into_iter()
+            .find(|u| u.credentials.iter().any(|c| c.passkey.cred_id().as_ref() == credential_id))
     }
 
     pub fn exists(email: &str) -> Result<bool> {
-        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.contains_key(email))
+        Ok(storage()?.get_user(email)?.is_some())
     }
 
     pub fn verify(email: &str) -> Result<()> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+        update(email, &mut |user| {
+            user.verified = true;
+            Ok(())
+        })
+    }
 
-        let user = db.get_mut(email).ok_or(anyhow!("User not found"))?;
-        if user.verified {
-            return Ok(());
-        }
+    /// Met à jour les comptes créés par une version antérieure: attribue un user handle
+    /// à ceux qui n'en ont pas et déplace l'ancienne passkey unique dans `credentials`.
+    ///
+    /// Une passkey déplacée reste liée, chez l'authentificateur, à l'identifiant aléatoire
+    /// choisi lors de son enregistrement et non au nouveau user handle.
+    pub(super) fn migrate_legacy_users() -> Result<()> {
+        let legacy = storage()?
+            .users()?
+            .into_iter()
+            .filter(|u| u.user_handle.is_nil() || u.passkey.is_some());
 
-        user.verified = true;
-        save(&db)?;
+        for user in legacy {
+            update(&user.email, &mut |user| {
+                if user.user_handle.is_nil() {
+                    user.user_handle = Uuid::new_v4();
+                }
+                if let Some(passkey) = user.passkey.take() {
+                    if !user.credentials.iter().any(|c| c.passkey.cred_id() == passkey.cred_id()) {
+                        // La date d'enregistrement n'était pas conservée: on retient celle de la migration
+                        user.credentials.push(Credential::new("Passkey", passkey));
+                    }
+                }
+                Ok(())
+            })?;
+        }
         Ok(())
     }
 
-    pub fn load() -> Result<()> {
-        super::load(&DB, consts::USERS_DB_PATH)
-    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::testing::{self, Authenticator};
+        use crate::utils::webauthn::{
+            begin_authentication, begin_discoverable_authentication, begin_registration,
+            complete_authentication, complete_discoverable_authentication, complete_registration,
+            StoredRegistrationState,
+        };
+        use webauthn_rs::prelude::Base64UrlSafeData;
+
+        /// Compte enregistré par une version antérieure: une seule passkey, liée à un
+        /// identifiant aléatoire, et pas de user handle. Retourne cet identifiant.
+        async fn create_legacy_user(email: &str, authenticator: &mut Authenticator) -> Uuid {
+            let registration_id = Uuid::new_v4();
+            let (public_key, registration_state) = begin_registration(registration_id, email, email, Vec::new())
+                .await
+                .unwrap();
+            let response = authenticator.register(&public_key);
+            let state = StoredRegistrationState {
+                registration_state,
+                email: email.to_string(),
+                user_handle: registration_id,
+            };
+            let passkey = complete_registration(email, &response, &state).await.unwrap();
 
-    fn save(db: &Db) -> Result<()> {
-        super::save(db, consts::USERS_DB_PATH)
+            let user = User {
+                first_name: "Legacy".to_string(),
+                last_name: "User".to_string(),
+                email: email.to_string(),
+                user_handle: Uuid::nil(),
+                credentials: Vec::new(),
+                passkey: Some(passkey),
+                verified: true,
+                stash: Vec::new(),
+                reactions: HashMap::new(),
+            };
+            assert!(storage().unwrap().insert_user(user).unwrap());
+            registration_id
+        }
+
+        #[tokio::test]
+        async fn migrated_user_can_log_in() {
+            testing::init();
+            let email = testing::unique_email("legacy");
+            let mut authenticator = Authenticator::new();
+            let registration_id = create_legacy_user(&email, &mut authenticator).await;
+
+            migrate_legacy_users().unwrap();
+            let user = get(&email).unwrap();
+            assert!(!user.user_handle.is_nil());
+            assert!(user.passkey.is_none());
+            assert_eq!(user.credentials.len(), 1);
+
+            // Connexion avec email: l'authentificateur renvoie l'ancien identifiant
+            let (public_key, state) = begin_authentication(&email).await.unwrap();
+            let mut response = authenticator.authenticate(&public_key);
+            response.response.user_handle = Some(Base64UrlSafeData::from(registration_id.as_bytes().to_vec()));
+            complete_authentication(&email, &response, &state).await.unwrap();
+
+            // Connexion sans nom d'utilisateur
+            let (public_key, state) = begin_discoverable_authentication().await.unwrap();
+            let mut response = authenticator.authenticate(&public_key);
+            response.response.user_handle = Some(Base64UrlSafeData::from(registration_id.as_bytes().to_vec()));
+            assert_eq!(complete_discoverable_authentication(&response, state).await.unwrap(), email);
+
+            assert!(get(&email).unwrap().credentials[0].last_used_at.is_some());
+        }
     }
 }
 
 /// Gestion des tokens
 pub mod token {
     use super::*;
-    use once_cell::sync::Lazy;
+    use chrono::{DateTime, Duration, Utc};
+    use sha2::{Digest, Sha256};
 
-    type Db = HashMap<String, String>;
-    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    /// Usage auquel un token est destiné
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Purpose {
+        /// Vérification de l'adresse email à l'inscription
+        Verify,
+        /// Récupération de compte
+        Recover,
+    }
+
+    impl Purpose {
+        /// Durée de validité d'un token pour cet usage
+        pub fn ttl(self) -> Duration {
+            match self {
+                Purpose::Verify => Duration::minutes(consts::VERIFY_TOKEN_TTL_MINUTES),
+                Purpose::Recover => Duration::minutes(consts::RECOVER_TOKEN_TTL_MINUTES),
+            }
+        }
+
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Purpose::Verify => "verify",
+                Purpose::Recover => "recover",
+            }
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Token {
+        pub email: String,
+        pub purpose: Purpose,
+        pub created_at: DateTime<Utc>,
+        pub ttl_seconds: i64,
+    }
 
-    pub fn generate(email: &str) -> Result<String> {
+    impl Token {
+        pub fn expires_at(&self) -> DateTime<Utc> {
+            self.created_at + Duration::seconds(self.ttl_seconds)
+        }
+
+        pub fn is_expired(&self) -> bool {
+            Utc::now() >= self.expires_at()
+        }
+    }
+
+    /// Les tokens sont indexés par leur empreinte SHA-256: le stockage ne contient
+    /// aucun lien utilisable.
+    fn hash(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    pub fn generate(email: &str, purpose: Purpose) -> Result<String> {
         let token = uuid::Uuid::new_v4().to_string();
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        db.insert(token.clone(), email.to_string());
+        let entry = Token {
+            email: email.to_string(),
+            purpose,
+            created_at: Utc::now(),
+            ttl_seconds: purpose.ttl().num_seconds(),
+        };
+        storage()?.insert_token(&hash(&token), entry)?;
         Ok(token)
     }
 
-    pub fn consume(token: &str) -> Result<String> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        db.remove(token).ok_or_else(|| anyhow!("Token not found"))
+    /// Consomme un token et retourne l'email associé.
+    /// Un token destiné à un autre usage est refusé sans être consommé.
+    pub fn consume(token: &str, purpose: Purpose) -> Result<String> {
+        let entry = storage()?
+            .take_token(&hash(token), purpose)?
+            .ok_or_else(|| anyhow!("Token not found"))?;
+
+        if entry.is_expired() {
+            return Err(anyhow!("Token expired"));
+        }
+        Ok(entry.email)
+    }
+
+    /// Supprime les tokens expirés et retourne leur nombre
+    pub fn purge_expired() -> Result<usize> {
+        storage()?.purge_expired_tokens(Utc::now())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn consume_returns_email_once() {
+            init_for_tests();
+            let token = generate("once@example.com", Purpose::Verify).unwrap();
+
+            assert_eq!(consume(&token, Purpose::Verify).unwrap(), "once@example.com");
+            assert!(consume(&token, Purpose::Verify).is_err());
+        }
+
+        #[test]
+        fn consume_refuses_wrong_purpose_without_consuming() {
+            init_for_tests();
+            let token = generate("purpose@example.com", Purpose::Verify).unwrap();
+
+            assert!(consume(&token, Purpose::Recover).is_err());
+            assert_eq!(consume(&token, Purpose::Verify).unwrap(), "purpose@example.com");
+        }
+
+        #[test]
+        fn consume_refuses_expired_token() {
+            init_for_tests();
+            let token = uuid::Uuid::new_v4().to_string();
+            let entry = Token {
+                email: "expired@example.com".to_string(),
+                purpose: Purpose::Recover,
+                created_at: Utc::now() - Purpose::Recover.ttl() - Duration::seconds(1),
+                ttl_seconds: Purpose::Recover.ttl().num_seconds(),
+            };
+            storage().unwrap().insert_token(&hash(&token), entry).unwrap();
+
+            assert!(consume(&token, Purpose::Recover).is_err());
+        }
+
+        #[test]
+        fn unknown_token_is_refused() {
+            init_for_tests();
+            assert!(consume("not-a-token", Purpose::Verify).is_err());
+        }
+
+        #[test]
+        fn only_token_hash_is_stored() {
+            init_for_tests();
+            let token = generate("hashed@example.com", Purpose::Recover).unwrap();
+
+            let data_dir = std::env::temp_dir().join(format!("lab02-db-{}", std::process::id()));
+            let stored = std::fs::read_to_string(data_dir.join(consts::TOKENS_DB_FILE)).unwrap();
+            assert!(stored.contains(&hash(&token)));
+            assert!(!stored.contains(&token));
+
+            assert!(storage().unwrap().take_token(&token, Purpose::Recover).unwrap().is_none());
+            assert_eq!(consume(&token, Purpose::Recover).unwrap(), "hashed@example.com");
+        }
     }
 }
 
 // Gestion des emails
 pub mod email {
     use super::*;
-    use once_cell::sync::Lazy;
+    use chrono::{DateTime, Duration, Utc};
+
+    /// État d'un email dans la file d'envoi
+    #[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Status {
+        /// En attente d'envoi ou d'un nouvel essai
+        Pending,
+        /// Remis au transport. Les emails antérieurs à la file étaient envoyés immédiatement.
+        #[default]
+        Sent,
+        /// Abandonné après `OUTBOX_MAX_ATTEMPTS` échecs
+        Dead,
+    }
+
+    impl Status {
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Status::Pending => "pending",
+                Status::Sent => "sent",
+                Status::Dead => "dead",
+            }
+        }
+    }
 
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct Email {
         pub pk: u64,
         pub to: String,
         pub subject: String,
+        /// Version texte
         pub body: String,
+        /// Version HTML, si l'email en a une
+        #[serde(default)]
+        pub html: Option<String>,
+        #[serde(default)]
+        pub status: Status,
+        /// Nombre d'essais d'envoi échoués
+        #[serde(default)]
+        pub attempts: u32,
+        /// Date à partir de laquelle l'email peut être (ré)essayé
+        #[serde(default)]
+        pub next_attempt_at: DateTime<Utc>,
+        #[serde(default)]
+        pub last_error: Option<String>,
+        #[serde(default)]
+        pub created_at: DateTime<Utc>,
+        #[serde(default)]
+        pub sent_at: Option<DateTime<Utc>>,
     }
 
-    #[derive(Default, Serialize, Deserialize)]
-    struct Db {
-        pub next_pk: u64,
-        pub emails: HashMap<u64, Email>,
+    impl Email {
+        /// Enregistre un échec d'envoi: l'email est réessayé plus tard, avec un délai qui
+        /// double à chaque essai, ou abandonné après `OUTBOX_MAX_ATTEMPTS` échecs.
+        pub(super) fn record_failure(&mut self, error: &str, now: DateTime<Utc>) {
+            self.attempts += 1;
+            self.last_error = Some(error.to_string());
+
+            if self.attempts >= consts::OUTBOX_MAX_ATTEMPTS {
+                self.status = Status::Dead;
+                return;
+            }
+
+            let delay = consts::OUTBOX_RETRY_BASE_SECS
+                .saturating_mul(1 << (self.attempts - 1).min(16))
+                .min(consts::OUTBOX_RETRY_MAX_SECS);
+            self.next_attempt_at = now + Duration::seconds(delay);
+        }
     }
 
-    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    /// Ajoute un email à la file d'envoi
+    pub fn add(to: &str, subject: &str, body: &str, html: Option<&str>) -> Result<()> {
+        storage()?.add_email(to, subject, body, html)?;
+        Ok(())
+    }
 
-    pub fn add(to: &str, subject: &str, body: &str) -> Result<()> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+    /// Tous les emails, par ordre d'ajout
+    pub fn all() -> Result<Vec<Email>> {
+        storage()?.emails()
+    }
 
-        let pk = db.next_pk;
-        db.next_pk += 1;
-        let email = Email {
-            pk,
-            to: to.to_string(),
-            subject: subject.to_string(),
-            body: body.to_string(),
-        };
+    /// Emails en attente dont l'envoi peut être tenté maintenant, les plus anciens en premier
+    pub fn due(limit: usize) -> Result<Vec<Email>> {
+        storage()?.due_emails(Utc::now(), limit)
+    }
 
-        db.emails.insert(pk, email);
-        save(&db)?;
+    pub fn mark_sent(pk: u64) -> Result<()> {
+        storage()?.update_email(pk, &mut |email| {
+            email.status = Status::Sent;
+            email.sent_at = Some(Utc::now());
+            Ok(())
+        })?;
         Ok(())
     }
 
-    pub fn load() -> Result<()> {
-        super::load(&DB, consts::EMAILS_DB_PATH)
+    /// Enregistre un échec d'envoi et retourne le nouvel état de l'email
+    pub fn mark_failed(pk: u64, error: &str) -> Result<Status> {
+        let mut status = Status::Pending;
+        storage()?.update_email(pk, &mut |email| {
+            email.record_failure(error, Utc::now());
+            status = email.status;
+            Ok(())
+        })?;
+        Ok(status)
     }
 
-    fn save(db: &Db) -> Result<()> {
-        super::save(db, consts::EMAILS_DB_PATH)
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn pending_email() -> Email {
+            let now = Utc::now();
+            Email {
+                pk: 0,
+                to: "user@example.com".to_string(),
+                subject: "Subject".to_string(),
+                body: "Body".to_string(),
+                html: None,
+                status: Status::Pending,
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+                created_at: now,
+                sent_at: None,
+            }
+        }
+
+        #[test]
+        fn retry_delay_doubles_after_each_failure() {
+            let mut email = pending_email();
+            let now = Utc::now();
+            let mut expected = consts::OUTBOX_RETRY_BASE_SECS;
+
+            for attempt in 1..consts::OUTBOX_MAX_ATTEMPTS {
+                email.record_failure("timeout", now);
+                assert_eq!(email.attempts, attempt);
+                assert_eq!(email.status, Status::Pending);
+                assert_eq!(email.next_attempt_at, now + Duration::seconds(expected));
+                assert!(expected <= consts::OUTBOX_RETRY_MAX_SECS);
+                expected = (expected * 2).min(consts::OUTBOX_RETRY_MAX_SECS);
+            }
+        }
+
+        #[test]
+        fn email_is_dead_after_max_attempts() {
+            let mut email = pending_email();
+            for _ in 0..consts::OUTBOX_MAX_ATTEMPTS {
+                email.record_failure("timeout", Utc::now());
+            }
+
+            assert_eq!(email.status, Status::Dead);
+            assert_eq!(email.attempts, consts::OUTBOX_MAX_ATTEMPTS);
+            assert_eq!(email.last_error.as_deref(), Some("timeout"));
+        }
     }
 }
 
+// Gestion des posts
 pub mod post {
     use super::*;
+    use std::collections::HashSet;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    /// Réaction d'un utilisateur à un post
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Reaction {
+        Like,
+        Dislike,
+    }
 
+    /// Modèle représentant un post avec ses compteurs de réactions
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct Post {
-        pub id: String,
-        pub text: String,
-        pub image_path: String,
-        pub likes: i32,
+        pub id: Uuid,
+        /// Email de l'auteur
+        #[serde(default)]
+        pub author: String,
+        pub content: String,
+        pub image_path: Option<String>,
+        #[serde(default)]
+        pub like_count: u32,
+        #[serde(default)]
+        pub dislike_count: u32,
+        #[serde(default)]
+        pub comment_count: u32,
+        /// Hashtags du texte, en minuscules
+        #[serde(default)]
+        pub tags: Vec<String>,
+        /// Emails des utilisateurs mentionnés
+        #[serde(default)]
+        pub mentions: Vec<String>,
+        #[serde(default)]
+        pub created_at: DateTime<Utc>,
+        #[serde(default)]
+        pub edited_at: Option<DateTime<Utc>>,
+    }
+
+    /// Ordre du fil d'actualité
+    #[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Sort {
+        /// Les plus récents en premier
+        #[default]
+        New,
+        /// Meilleur score (likes moins dislikes) en premier, puis les plus récents
+        Score,
+    }
+
+    /// Sélection des posts d'un fil
+    #[derive(Clone, Copy)]
+    pub enum Filter<'a> {
+        All,
+        /// Posts portant ce hashtag
+        Tag(&'a str),
+        /// Posts mentionnant cet utilisateur
+        Mentioning(&'a str),
+        /// Posts dont l'identifiant figure dans ces favoris
+        Bookmarked(&'a [String]),
+    }
+
+    impl Filter<'_> {
+        pub(super) fn matches(&self, post: &Post) -> bool {
+            match self {
+                Filter::All => true,
+                Filter::Tag(tag) => post.tags.iter().any(|t| t == tag),
+                Filter::Mentioning(email) => post.mentions.iter().any(|m| m == email),
+                Filter::Bookmarked(stash) => stash.contains(&post.id.to_string()),
+            }
+        }
+    }
+
+    /// Page du fil d'actualité
+    pub struct Page {
+        pub posts: Vec<Post>,
+        /// Curseur à passer pour obtenir la page suivante, `None` s'il n'y en a plus
+        pub next_cursor: Option<String>,
+    }
+
+    /// Position dans le fil, encodée en base64url dans le curseur.
+    /// Le fil est trié par curseur décroissant.
+    #[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Cursor {
+        /// Score du post avec [`Sort::Score`], 0 avec [`Sort::New`]
+        pub(super) score: i64,
+        pub(super) created_at: DateTime<Utc>,
+        pub(super) id: Uuid,
+    }
+
+    impl Cursor {
+        pub(super) fn of(post: &Post, sort: Sort) -> Self {
+            Cursor {
+                score: match sort {
+                    Sort::New => 0,
+                    Sort::Score => i64::from(post.like_count) - i64::from(post.dislike_count),
+                },
+                created_at: post.created_at,
+                id: post.id,
+            }
+        }
+
+        fn encode(&self) -> Result<String> {
+            Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(self)?))
+        }
+
+        fn decode(cursor: &str) -> Result<Self> {
+            let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| anyhow!("Invalid cursor"))?;
+            serde_json::from_slice(&bytes).map_err(|_| anyhow!("Invalid cursor"))
+        }
+    }
+
+    /// Retourne tous les posts dans l'ordre de création
+    pub fn all() -> Result<Vec<Post>> {
+        storage()?.posts()
+    }
+
+    /// Retourne une page du fil, commençant après `cursor` (ou au début du fil)
+    pub fn page(filter: Filter, sort: Sort, cursor: Option<&str>, limit: usize) -> Result<Page> {
+        let after = cursor.map(Cursor::decode).transpose()?;
+
+        // Un post de plus que demandé indique s'il existe une page suivante
+        let mut posts = storage()?.feed(filter, sort, after.as_ref(), limit + 1)?;
+        let has_more = posts.len() > limit;
+        posts.truncate(limit);
+
+        let next_cursor = match posts.last() {
+            Some(post) if has_more => Some(Cursor::of(post, sort).encode()?),
+            _ => None,
+        };
+
+        Ok(Page { posts, next_cursor })
+    }
+
+    /// Crée un post. `tags` et `mentions` sont extraits du texte par l'appelant.
+    pub fn create(
+        author: &str,
+        content: &str,
+        image_path: Option<&str>,
+        tags: Vec<String>,
+        mentions: Vec<String>,
+    ) -> Result<Uuid> {
+        let post = Post {
+            id: Uuid::new_v4(),
+            author: author.to_string(),
+            content: content.to_string(),
+            image_path: image_path.map(|path| path.to_string()),
+            like_count: 0,
+            dislike_count: 0,
+            comment_count: 0,
+            tags,
+            mentions,
+            created_at: Utc::now(),
+            edited_at: None,
+        };
+
+        let id = post.id;
+        storage()?.insert_post(post.clone())?;
+        search_index()?.insert(&post);
+        Ok(id)
+    }
+
+    pub fn get(id: Uuid) -> Result<Option<Post>> {
+        storage()?.get_post(id)
+    }
+
+    /// Remplace le contenu d'un post et enregistre la date de modification.
+    /// Retourne `false` si le post n'existe pas.
+    pub fn edit(id: Uuid, content: &str, tags: Vec<String>, mentions: Vec<String>) -> Result<bool> {
+        let mut edited = None;
+        let found = storage()?.update_post(id, &mut |post| {
+            post.content = content.to_string();
+            post.tags = tags.clone();
+            post.mentions = mentions.clone();
+            post.edited_at = Some(Utc::now());
+            edited = Some(post.clone());
+            Ok(())
+        })?;
+
+        if let Some(post) = edited {
+            search_index()?.insert(&post);
+        }
+        Ok(found)
+    }
+
+    /// Supprime un post avec ses commentaires, le retire des favoris et réactions des
+    /// utilisateurs, et retourne le post supprimé s'il existait. `release_image` reçoit le
+    /// chemin de son image si plus aucun post ne la référence (voir [`Storage::delete_post`]).
+    pub fn delete(id: Uuid, release_image: &mut dyn FnMut(&str)) -> Result<Option<Post>> {
+        let post = storage()?.delete_post(id, release_image)?;
+        search_index()?.remove(id);
+        Ok(post)
+    }
+
+    /// Résultats d'une recherche
+    pub struct SearchResults {
+        pub posts: Vec<Post>,
+        /// Nombre total de posts correspondants
+        pub total: usize,
+    }
+
+    /// Recherche plein texte dans le contenu des posts (voir [`super::search`]).
+    /// Les résultats sont triés par pertinence; `offset` et `limit` sélectionnent une page.
+    pub fn search(query: &str, offset: usize, limit: usize) -> Result<SearchResults> {
+        let ids = SEARCH.read().or(Err(anyhow!("Search index poisoned")))?.search(query);
+        let storage = storage()?;
+
+        let mut posts = Vec::new();
+        for id in ids.iter().skip(offset).take(limit) {
+            posts.extend(storage.get_post(*id)?);
+        }
+
+        Ok(SearchResults { posts, total: ids.len() })
+    }
+
+    /// Construit l'index de recherche à partir des posts existants
+    pub(super) fn build_search_index() -> Result<()> {
+        *search_index()? = super::search::SearchIndex::build(&all()?);
+        Ok(())
+    }
+
+    /// Chemins stockés des images référencées par au moins un post.
+    ///
+    /// Parcourt tous les posts: réservé au nettoyage périodique des médias. La suppression
+    /// d'un post libère son image sans parcours (voir [`delete`]).
+    pub fn scan_image_paths() -> Result<HashSet<String>> {
+        Ok(storage()?.posts()?.into_iter().filter_map(|p| p.image_path).collect())
+    }
+
+    /// Enregistre la réaction d'un utilisateur. Répéter la même réaction l'annule.
+    /// Retourne `false` si le post n'existe pas.
+    pub fn react(email: &str, id: Uuid, reaction: Reaction) -> Result<bool> {
+        storage()?.react(email, id, reaction)
     }
-}
 
-/// Fonctions de sauvegarde et chargement YAML
-fn save<T: Serialize>(db: &T, path: &str) -> Result<()> {
-    let path_obj = Path::new(path);
+    /// Applique le changement de réaction d'un utilisateur aux compteurs du post et
+    /// retourne la nouvelle réaction de l'utilisateur.
+    pub(super) fn apply_reaction(
+        post: &mut Post,
+        current: Option<Reaction>,
+        requested: Reaction,
+    ) -> Option<Reaction> {
+        match current {
+            Some(Reaction::Like) => post.like_count = post.like_count.saturating_sub(1),
+            Some(Reaction::Dislike) => post.dislike_count = post.dislike_count.saturating_sub(1),
+            None => (),
+        }
 
-    // Crée le dossier parent s'il n'existe pas
-    if let Some(parent_dir) = path_obj.parent() {
-        if !parent_dir.exists() {
-            create_dir_all(parent_dir).or(Err(anyhow!("Failed to create directory")))?;
+        if current == Some(requested) {
+            return None;
         }
+
+        match requested {
+            Reaction::Like => post.like_count += 1,
+            Reaction::Dislike => post.dislike_count += 1,
+        }
+        Some(requested)
     }
 
-    let file = File::create(path_obj)?;
-    to_writer(file, db).or(Err(anyhow!("Failed to serialize DB")))?;
-    Ok(())
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Crée `count` posts portant un hashtag propre au test
+        fn create_tagged(count: usize) -> (String, Vec<Uuid>) {
+            init_for_tests();
+            let tag = format!("t{}", Uuid::new_v4().simple());
+            let ids = (0..count)
+                .map(|i| {
+                    let content = format!("post {}", i);
+                    create("feed@example.com", &content, None, vec![tag.clone()], Vec::new()).unwrap()
+                })
+                .collect();
+            (tag, ids)
+        }
+
+        #[test]
+        fn cursor_round_trip() {
+            let cursor = Cursor {
+                score: -3,
+                created_at: Utc::now(),
+                id: Uuid::new_v4(),
+            };
+            let decoded = Cursor::decode(&cursor.encode().unwrap()).unwrap();
+            assert!(decoded == cursor);
+        }
+
+        #[test]
+        fn cursor_rejects_garbage() {
+            assert!(Cursor::decode("not base64!").is_err());
+            assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode(b"{}")).is_err());
+            assert!(page(Filter::All, Sort::New, Some("garbage"), 10).is_err());
+        }
+
+        #[test]
+        fn page_walks_feed_without_gaps_or_duplicates() {
+            let (tag, ids) = create_tagged(5);
+            let mut expected: Vec<Post> = ids.iter().map(|id| get(*id).unwrap().unwrap()).collect();
+            expected.sort_by_key(|p| std::cmp::Reverse((p.created_at, p.id)));
+
+            let mut seen = Vec::new();
+            let mut cursor = None;
+            let mut pages = 0;
+            loop {
+                let page = page(Filter::Tag(&tag), Sort::New, cursor.as_deref(), 2).unwrap();
+                assert!(page.posts.len() <= 2);
+                seen.extend(page.posts.iter().map(|p| p.id));
+                pages += 1;
+
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+
+            assert_eq!(pages, 3);
+            assert_eq!(seen, expected.iter().map(|p| p.id).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn full_last_page_has_no_next_cursor() {
+            let (tag, _) = create_tagged(2);
+
+            let page = page(Filter::Tag(&tag), Sort::Score, None, 2).unwrap();
+            assert_eq!(page.posts.len(), 2);
+            assert!(page.next_cursor.is_none());
+        }
+    }
 }
 
-fn load<T: for<'de> Deserialize<'de> + Default>(db: &RwLock<T>, path: &str) -> Result<()> {
-    // Chargement de la base de données depuis le fichier YAML
-    if let Ok(file) = File::open(path) {
-        let db_content: T = serde_yaml::from_reader(file).unwrap_or_default();
-        let mut db = db.write().or(Err(anyhow!("DB poisoned")))?;
-        *db = db_content;
-    } else {
-        let mut db = db.write().or(Err(anyhow!("DB poisoned")))?;
-        *db = T::default();
-    }
-    Ok(())
+// Gestion des commentaires
+pub mod comment {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    /// Commentaire d'un post. Une réponse référence un commentaire de premier niveau.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Comment {
+        pub id: Uuid,
+        pub post_id: Uuid,
+        /// Commentaire auquel celui-ci répond
+        pub parent_id: Option<Uuid>,
+        /// Email de l'auteur
+        pub author: String,
+        pub content: String,
+        pub created_at: DateTime<Utc>,
+    }
+
+    /// Retourne les commentaires d'un post dans l'ordre de création
+    pub fn for_post(post_id: Uuid) -> Result<Vec<Comment>> {
+        storage()?.comments(post_id)
+    }
+
+    /// Résultat de l'ajout d'un commentaire
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum Outcome {
+        Created(Uuid),
+        /// Le post n'existe pas
+        PostNotFound,
+        /// Le commentaire parent n'existe pas, appartient à un autre post ou est une réponse
+        InvalidParent,
+    }
+
+    /// Ajoute un commentaire à un post, ou une réponse à un commentaire de premier niveau
+    pub fn create(author: &str, post_id: Uuid, parent_id: Option<Uuid>, content: &str) -> Result<Outcome> {
+        if let Some(parent_id) = parent_id {
+            // Un seul niveau de réponses
+            let valid = storage()?
+                .get_comment(parent_id)?
+                .is_some_and(|parent| parent.post_id == post_id && parent.parent_id.is_none());
+            if !valid {
+                return Ok(Outcome::InvalidParent);
+            }
+        }
+
+        let comment = Comment {
+            id: Uuid::new_v4(),
+            post_id,
+            parent_id,
+            author: author.to_string(),
+            content: content.to_string(),
+            created_at: Utc::now(),
+        };
+
+        let id = comment.id;
+        match storage()?.insert_comment(comment)? {
+            true => Ok(Outcome::Created(id)),
+            false => Ok(Outcome::PostNotFound),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn create_post() -> Uuid {
+            init_for_tests();
+            post::create("author@example.com", "post", None, Vec::new(), Vec::new()).unwrap()
+        }
+
+        #[test]
+        fn replies_need_a_top_level_parent_on_the_same_post() {
+            let post_id = create_post();
+            let other_post_id = create_post();
+            let author = "reader@example.com";
+
+            let Outcome::Created(top) = create(author, post_id, None, "top").unwrap() else {
+                panic!("comment not created");
+            };
+            let Outcome::Created(reply) = create(author, post_id, Some(top), "reply").unwrap() else {
+                panic!("reply not created");
+            };
+
+            assert_eq!(create(author, post_id, Some(reply), "nested").unwrap(), Outcome::InvalidParent);
+            assert_eq!(create(author, other_post_id, Some(top), "elsewhere").unwrap(), Outcome::InvalidParent);
+            assert_eq!(create(author, post_id, Some(Uuid::new_v4()), "orphan").unwrap(), Outcome::InvalidParent);
+        }
+
+        #[test]
+        fn comment_on_missing_post_is_reported() {
+            init_for_tests();
+            assert_eq!(create("reader@example.com", Uuid::new_v4(), None, "hello").unwrap(), Outcome::PostNotFound);
+        }
+    }
 }
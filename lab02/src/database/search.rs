@@ -0,0 +1,284 @@
+//! Index inversé en mémoire pour la recherche plein texte dans les posts.
+//! L'index est construit au démarrage à partir du stockage, puis tenu à jour à chaque
+//! création, modification ou suppression de post.
+//!
+//! Syntaxe des requêtes: les mots sont combinés (tous doivent apparaître), `"deux mots"`
+//! recherche une phrase exacte et `mot*` recherche un préfixe.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use uuid::Uuid;
+use super::post::Post;
+
+/// Longueur minimale d'un préfixe, pour éviter d'étendre la requête à tout le vocabulaire
+const MIN_PREFIX_LENGTH: usize = 2;
+
+/// Élément d'une requête
+#[derive(Debug, PartialEq)]
+enum Clause {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    /// Pour chaque mot: positions du mot dans chaque post
+    postings: BTreeMap<String, HashMap<Uuid, Vec<usize>>>,
+    /// Mots de chaque post, pour pouvoir le retirer de l'index
+    documents: HashMap<Uuid, Vec<String>>,
+}
+
+/// Découpe un texte en mots normalisés (minuscules, lettres et chiffres uniquement)
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Analyse une requête. Les clauses vides sont ignorées.
+fn parse_query(query: &str) -> Vec<Clause> {
+    let mut clauses = Vec::new();
+
+    // Les segments impairs sont entre guillemets
+    for (i, segment) in query.split('"').enumerate() {
+        if i % 2 == 1 {
+            match tokenize(segment).as_slice() {
+                [] => (),
+                [word] => clauses.push(Clause::Term(word.clone())),
+                words => clauses.push(Clause::Phrase(words.to_vec())),
+            }
+            continue;
+        }
+
+        for word in segment.split_whitespace() {
+            let mut tokens = tokenize(word);
+
+            // Seul le dernier mot d'un segment comme `foo-ba*` est un préfixe
+            let prefix = if word.ends_with('*') { tokens.pop() } else { None };
+            clauses.extend(tokens.into_iter().map(Clause::Term));
+
+            match prefix {
+                Some(prefix) if prefix.chars().count() >= MIN_PREFIX_LENGTH => clauses.push(Clause::Prefix(prefix)),
+                Some(prefix) => clauses.push(Clause::Term(prefix)),
+                None => (),
+            }
+        }
+    }
+    clauses
+}
+
+impl SearchIndex {
+    /// Construit l'index à partir de tous les posts
+    pub fn build(posts: &[Post]) -> Self {
+        let mut index = SearchIndex::default();
+        for post in posts {
+            index.insert(post);
+        }
+        index
+    }
+
+    /// Indexe un post, en remplaçant sa version précédente
+    pub fn insert(&mut self, post: &Post) {
+        self.remove(post.id);
+
+        let words = tokenize(&post.content);
+        for (position, word) in words.iter().enumerate() {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .entry(post.id)
+                .or_default()
+                .push(position);
+        }
+        self.documents.insert(post.id, words);
+    }
+
+    /// Retire un post de l'index
+    pub fn remove(&mut self, id: Uuid) {
+        let Some(words) = self.documents.remove(&id) else {
+            return;
+        };
+
+        for word in words.into_iter().collect::<HashSet<_>>() {
+            if let Some(posts) = self.postings.get_mut(&word) {
+                posts.remove(&id);
+                if posts.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Poids d'un mot: les mots rares comptent davantage
+    fn idf(&self, word: &str) -> f64 {
+        let matching = self.postings.get(word).map_or(0, |posts| posts.len());
+        (1.0 + self.documents.len() as f64 / (1.0 + matching as f64)).ln()
+    }
+
+    /// Nombre d'occurrences de la clause dans chaque post qui la contient
+    fn matches(&self, clause: &Clause) -> HashMap<Uuid, f64> {
+        match clause {
+            Clause::Term(word) => self
+                .postings
+                .get(word)
+                .map(|posts| {
+                    let idf = self.idf(word);
+                    posts.iter().map(|(id, positions)| (*id, positions.len() as f64 * idf)).collect()
+                })
+                .unwrap_or_default(),
+            Clause::Prefix(prefix) => {
+                let mut scores = HashMap::new();
+                for (word, posts) in self.postings.range(prefix.clone()..) {
+                    if !word.starts_with(prefix.as_str()) {
+                        break;
+                    }
+                    let idf = self.idf(word);
+                    for (id, positions) in posts {
+                        *scores.entry(*id).or_insert(0.0) += positions.len() as f64 * idf;
+                    }
+                }
+                scores
+            }
+            Clause::Phrase(words) => {
+                let Some(first) = self.postings.get(&words[0]) else {
+                    return HashMap::new();
+                };
+                let idf: f64 = words.iter().map(|word| self.idf(word)).sum();
+
+                first
+                    .iter()
+                    .filter_map(|(id, starts)| {
+                        let occurrences = starts
+                            .iter()
+                            .filter(|&&start| {
+                                words.iter().enumerate().skip(1).all(|(offset, word)| {
+                                    self.postings
+                                        .get(word)
+                                        .and_then(|posts| posts.get(id))
+                                        .is_some_and(|positions| positions.contains(&(start + offset)))
+                                })
+                            })
+                            .count();
+                        (occurrences > 0).then_some((*id, occurrences as f64 * idf))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Recherche les posts correspondant à toutes les clauses de la requête.
+    /// Retourne les identifiants triés par pertinence décroissante.
+    pub fn search(&self, query: &str) -> Vec<Uuid> {
+        let clauses = parse_query(query);
+        let Some((first, rest)) = clauses.split_first() else {
+            return Vec::new();
+        };
+
+        let mut scores = self.matches(first);
+        for clause in rest {
+            let matches = self.matches(clause);
+            scores.retain(|id, score| match matches.get(id) {
+                Some(extra) => {
+                    *score += extra;
+                    true
+                }
+                None => false,
+            });
+        }
+
+        // Score normalisé par la longueur du post, puis ordre stable par identifiant
+        let mut ranked: Vec<(Uuid, f64)> = scores
+            .into_iter()
+            .map(|(id, score)| {
+                let length = self.documents.get(&id).map_or(1, |words| words.len().max(1));
+                (id, score / (length as f64).sqrt())
+            })
+            .collect();
+        ranked.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+        ranked.into_iter().map(|(id, _)| id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn term(word: &str) -> Clause {
+        Clause::Term(word.to_string())
+    }
+
+    fn post(content: &str) -> Post {
+        Post {
+            id: Uuid::new_v4(),
+            author: "search@example.com".to_string(),
+            content: content.to_string(),
+            image_path: None,
+            like_count: 0,
+            dislike_count: 0,
+            comment_count: 0,
+            tags: Vec::new(),
+            mentions: Vec::new(),
+            created_at: Utc::now(),
+            edited_at: None,
+        }
+    }
+
+    #[test]
+    fn parses_terms_phrases_and_prefixes() {
+        assert_eq!(
+            parse_query(r#"Rust "Hello  World" async*"#),
+            vec![
+                term("rust"),
+                Clause::Phrase(vec!["hello".to_string(), "world".to_string()]),
+                Clause::Prefix("async".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_word_phrase_is_a_term() {
+        assert_eq!(parse_query(r#""Rust""#), vec![term("rust")]);
+    }
+
+    #[test]
+    fn only_last_word_of_segment_is_a_prefix() {
+        assert_eq!(
+            parse_query("foo-ba*"),
+            vec![term("foo"), Clause::Prefix("ba".to_string())]
+        );
+    }
+
+    #[test]
+    fn short_prefix_is_a_term() {
+        assert_eq!(parse_query("a*"), vec![term("a")]);
+    }
+
+    #[test]
+    fn empty_clauses_are_ignored() {
+        assert!(parse_query(r#" "" * "!?" "#).is_empty());
+    }
+
+    #[test]
+    fn unclosed_quote_still_forms_a_phrase() {
+        assert_eq!(
+            parse_query(r#"say "hello there"#),
+            vec![
+                term("say"),
+                Clause::Phrase(vec!["hello".to_string(), "there".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn phrase_requires_adjacent_words() {
+        let adjacent = post("hello world");
+        let apart = post("hello big world");
+        let index = SearchIndex::build(&[adjacent.clone(), apart.clone()]);
+
+        assert_eq!(index.search(r#""hello world""#), vec![adjacent.id]);
+        assert_eq!(index.search("hello world").len(), 2);
+        assert_eq!(index.search("wor*").len(), 2);
+    }
+}
@@ -0,0 +1,642 @@
+//! Backend de stockage SQLite.
+//! Chaque enregistrement est stocké sous forme de document JSON, accompagné de colonnes
+//! indexées pour les champs utilisés dans les recherches et les tris. Les posts référencés
+//! par un utilisateur (favoris, réactions) sont tenus à jour dans `user_posts`.
+//! Les modifications sont faites dans des transactions.
+
+use std::{path::Path, sync::Mutex};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, TransactionBehavior};
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+use super::comment::Comment;
+use super::email::{Email, Status};
+use super::post::{apply_reaction, Cursor, Filter, Post, Reaction, Sort};
+use super::storage::Storage;
+use super::token::{Purpose, Token};
+use super::user::{forget_post, User};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        email TEXT PRIMARY KEY,
+        user_handle TEXT NOT NULL UNIQUE,
+        data TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS tokens (
+        hash TEXT PRIMARY KEY,
+        purpose TEXT NOT NULL,
+        expires_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS tokens_expires_at ON tokens (expires_at);
+
+    CREATE TABLE IF NOT EXISTS user_posts (
+        email TEXT NOT NULL,
+        post_id TEXT NOT NULL,
+        PRIMARY KEY (email, post_id)
+    );
+    CREATE INDEX IF NOT EXISTS user_posts_post_id ON user_posts (post_id);
+
+    CREATE TABLE IF NOT EXISTS emails (
+        pk INTEGER PRIMARY KEY,
+        status TEXT NOT NULL,
+        next_attempt_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS emails_due ON emails (status, next_attempt_at);
+
+    CREATE TABLE IF NOT EXISTS posts (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        id TEXT NOT NULL UNIQUE,
+        author TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        score INTEGER NOT NULL,
+        image_path TEXT,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS posts_author ON posts (author);
+    CREATE INDEX IF NOT EXISTS posts_image_path ON posts (image_path);
+    CREATE INDEX IF NOT EXISTS posts_new ON posts (created_at, id);
+    CREATE INDEX IF NOT EXISTS posts_score ON posts (score, created_at, id);
+
+    CREATE TABLE IF NOT EXISTS post_tags (
+        post_id TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (tag, post_id)
+    );
+    CREATE INDEX IF NOT EXISTS post_tags_post_id ON post_tags (post_id);
+
+    CREATE TABLE IF NOT EXISTS post_mentions (
+        post_id TEXT NOT NULL,
+        email TEXT NOT NULL,
+        PRIMARY KEY (email, post_id)
+    );
+    CREATE INDEX IF NOT EXISTS post_mentions_post_id ON post_mentions (post_id);
+
+    CREATE TABLE IF NOT EXISTS comments (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        id TEXT NOT NULL UNIQUE,
+        post_id TEXT NOT NULL,
+        parent_id TEXT,
+        author TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS comments_post_id ON comments (post_id);
+";
+
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Ouvre (ou crée) la base de données et applique le schéma
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent_dir) = path.parent() {
+            std::fs::create_dir_all(parent_dir).context("Failed to create directory")?;
+        }
+
+        let conn = Connection::open(path).context("Failed to open SQLite database")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA).context("Failed to apply SQLite schema")?;
+
+        Ok(SqliteStorage {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.conn.lock().or(Err(anyhow!("DB poisoned")))
+    }
+}
+
+/// Enregistre le document d'un utilisateur et les posts qu'il référence
+fn write_user(conn: &Connection, user: &User) -> Result<()> {
+    conn.execute(
+        "UPDATE users SET user_handle = ?2, data = ?3 WHERE email = ?1",
+        params![user.email, user.user_handle.to_string(), to_json(user)?],
+    )?;
+
+    conn.execute("DELETE FROM user_posts WHERE email = ?1", params![user.email])?;
+    let mut stmt = conn.prepare("INSERT OR IGNORE INTO user_posts (email, post_id) VALUES (?1, ?2)")?;
+    for post_id in user.stash.iter().cloned().chain(user.reactions.keys().map(Uuid::to_string)) {
+        stmt.execute(params![user.email, post_id])?;
+    }
+    Ok(())
+}
+
+/// Score d'un post pour le tri du fil (voir [`Cursor::of`])
+fn score(post: &Post) -> i64 {
+    i64::from(post.like_count) - i64::from(post.dislike_count)
+}
+
+/// Insère ou remplace le document d'un post, avec ses colonnes indexées, ses hashtags
+/// et ses mentions
+fn write_post(conn: &Connection, post: &Post) -> Result<()> {
+    let id = post.id.to_string();
+    conn.execute(
+        "INSERT INTO posts (id, author, created_at, score, image_path, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (id) DO UPDATE SET author = excluded.author, created_at = excluded.created_at,
+             score = excluded.score, image_path = excluded.image_path, data = excluded.data",
+        params![
+            id,
+            post.author,
+            post.created_at.timestamp_micros(),
+            score(post),
+            post.image_path,
+            to_json(post)?
+        ],
+    )?;
+
+    conn.execute("DELETE FROM post_tags WHERE post_id = ?1", params![id])?;
+    let mut stmt = conn.prepare("INSERT OR IGNORE INTO post_tags (post_id, tag) VALUES (?1, ?2)")?;
+    for tag in &post.tags {
+        stmt.execute(params![id, tag])?;
+    }
+
+    conn.execute("DELETE FROM post_mentions WHERE post_id = ?1", params![id])?;
+    let mut stmt = conn.prepare("INSERT OR IGNORE INTO post_mentions (post_id, email) VALUES (?1, ?2)")?;
+    for email in &post.mentions {
+        stmt.execute(params![id, email])?;
+    }
+    Ok(())
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String> {
+    serde_json::to_string(value).context("Failed to serialize record")
+}
+
+fn from_json<T: DeserializeOwned>(data: &str) -> Result<T> {
+    serde_json::from_str(data).context("Failed to deserialize record")
+}
+
+impl Storage for SqliteStorage {
+    fn users(&self) -> Result<Vec<User>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT data FROM users")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|data| from_json(&data?)).collect()
+    }
+
+    fn get_user(&self, email: &str) -> Result<Option<User>> {
+        let conn = self.conn()?;
+        conn.query_row("SELECT data FROM users WHERE email = ?1", params![email], |row| row.get::<_, String>(0))
+            .optional()?
+            .map(|data| from_json(&data))
+            .transpose()
+    }
+
+    fn find_user_by_handle(&self, user_handle: Uuid) -> Result<Option<User>> {
+        let conn = self.conn()?;
+        conn.query_row(
+            "SELECT data FROM users WHERE user_handle = ?1",
+            params![user_handle.to_string()],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|data| from_json(&data))
+        .transpose()
+    }
+
+    fn insert_user(&self, user: User) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let inserted = tx
+            .execute(
+                "INSERT INTO users (email, user_handle, data) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (email) DO NOTHING",
+                params![user.email, user.user_handle.to_string(), to_json(&user)?],
+            )
+            .context("Failed to insert user")?;
+        if inserted == 1 {
+            write_user(&tx, &user)?;
+        }
+        tx.commit()?;
+        Ok(inserted == 1)
+    }
+
+    fn update_user(&self, email: &str, f: &mut dyn FnMut(&mut User) -> Result<()>) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let data: Option<String> = tx
+            .query_row("SELECT data FROM users WHERE email = ?1", params![email], |row| row.get(0))
+            .optional()?;
+        let Some(data) = data else {
+            return Ok(false);
+        };
+
+        let mut user: User = from_json(&data)?;
+        f(&mut user)?;
+        write_user(&tx, &user)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn insert_token(&self, hash: &str, token: Token) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO tokens (hash, purpose, expires_at, data) VALUES (?1, ?2, ?3, ?4)",
+            params![hash, token.purpose.as_str(), token.expires_at().timestamp(), to_json(&token)?],
+        )?;
+        Ok(())
+    }
+
+    fn take_token(&self, hash: &str, purpose: Purpose) -> Result<Option<Token>> {
+        let conn = self.conn()?;
+        conn.query_row(
+            "DELETE FROM tokens WHERE hash = ?1 AND purpose = ?2 RETURNING data",
+            params![hash, purpose.as_str()],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|data| from_json(&data))
+        .transpose()
+    }
+
+    fn purge_expired_tokens(&self, now: DateTime<Utc>) -> Result<usize> {
+        let conn = self.conn()?;
+        let purged = conn.execute("DELETE FROM tokens WHERE expires_at <= ?1", params![now.timestamp()])?;
+        Ok(purged)
+    }
+
+    fn add_email(&self, to: &str, subject: &str, body: &str, html: Option<&str>) -> Result<Email> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let pk: u64 = tx.query_row("SELECT COALESCE(MAX(pk) + 1, 0) FROM emails", [], |row| row.get(0))?;
+        let now = Utc::now();
+        let email = Email {
+            pk,
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+            html: html.map(|html| html.to_string()),
+            status: Status::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            sent_at: None,
+        };
+
+        tx.execute(
+            "INSERT INTO emails (pk, status, next_attempt_at, data) VALUES (?1, ?2, ?3, ?4)",
+            params![pk, email.status.as_str(), email.next_attempt_at.timestamp(), to_json(&email)?],
+        )?;
+        tx.commit()?;
+        Ok(email)
+    }
+
+    fn emails(&self) -> Result<Vec<Email>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT data FROM emails ORDER BY pk")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|data| from_json(&data?)).collect()
+    }
+
+    fn due_emails(&self, now: DateTime<Utc>, limit: usize) -> Result<Vec<Email>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT data FROM emails WHERE status = ?1 AND next_attempt_at <= ?2 ORDER BY pk LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(
+            params![Status::Pending.as_str(), now.timestamp(), limit as i64],
+            |row| row.get::<_, String>(0),
+        )?;
+        rows.map(|data| from_json(&data?)).collect()
+    }
+
+    fn update_email(&self, pk: u64, f: &mut dyn FnMut(&mut Email) -> Result<()>) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let data: Option<String> = tx
+            .query_row("SELECT data FROM emails WHERE pk = ?1", params![pk], |row| row.get(0))
+            .optional()?;
+        let Some(data) = data else {
+            return Ok(false);
+        };
+
+        let mut email: Email = from_json(&data)?;
+        f(&mut email)?;
+        tx.execute(
+            "UPDATE emails SET status = ?2, next_attempt_at = ?3, data = ?4 WHERE pk = ?1",
+            params![pk, email.status.as_str(), email.next_attempt_at.timestamp(), to_json(&email)?],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn posts(&self) -> Result<Vec<Post>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT data FROM posts ORDER BY seq")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|data| from_json(&data?)).collect()
+    }
+
+    fn get_post(&self, id: Uuid) -> Result<Option<Post>> {
+        let conn = self.conn()?;
+        conn.query_row("SELECT data FROM posts WHERE id = ?1", params![id.to_string()], |row| row.get::<_, String>(0))
+            .optional()?
+            .map(|data| from_json(&data))
+            .transpose()
+    }
+
+    fn feed(&self, filter: Filter, sort: Sort, after: Option<&Cursor>, limit: usize) -> Result<Vec<Post>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        match filter {
+            Filter::All => (),
+            Filter::Tag(tag) => {
+                conditions.push("id IN (SELECT post_id FROM post_tags WHERE tag = ?)");
+                values.push(tag.to_string().into());
+            }
+            Filter::Mentioning(email) => {
+                conditions.push("id IN (SELECT post_id FROM post_mentions WHERE email = ?)");
+                values.push(email.to_string().into());
+            }
+            Filter::Bookmarked(stash) => {
+                conditions.push("id IN (SELECT value FROM json_each(?))");
+                values.push(to_json(&stash)?.into());
+            }
+        }
+
+        // Même ordre que `Cursor`: (score, date, id) décroissant, sans le score pour `Sort::New`
+        let (order, position) = match sort {
+            Sort::New => ("created_at DESC, id DESC", "(created_at, id) < (?, ?)"),
+            Sort::Score => ("score DESC, created_at DESC, id DESC", "(score, created_at, id) < (?, ?, ?)"),
+        };
+        if let Some(after) = after {
+            conditions.push(position);
+            if sort == Sort::Score {
+                values.push(after.score.into());
+            }
+            values.push(after.created_at.timestamp_micros().into());
+            values.push(after.id.to_string().into());
+        }
+        values.push((limit as i64).into());
+
+        let where_clause = match conditions.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", conditions.join(" AND ")),
+        };
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!("SELECT data FROM posts {} ORDER BY {} LIMIT ?", where_clause, order))?;
+        let rows = stmt.query_map(params_from_iter(values), |row| row.get::<_, String>(0))?;
+        rows.map(|data| from_json(&data?)).collect()
+    }
+
+    fn insert_post(&self, post: Post) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        write_post(&tx, &post)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_post(&self, id: Uuid, f: &mut dyn FnMut(&mut Post) -> Result<()>) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let data: Option<String> = tx
+            .query_row("SELECT data FROM posts WHERE id = ?1", params![id.to_string()], |row| row.get(0))
+            .optional()?;
+        let Some(data) = data else {
+            return Ok(false);
+        };
+
+        let mut post: Post = from_json(&data)?;
+        f(&mut post)?;
+        write_post(&tx, &post)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn delete_post(&self, id: Uuid, release_image: &mut dyn FnMut(&str)) -> Result<Option<Post>> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let data: Option<String> = tx
+            .query_row(
+                "DELETE FROM posts WHERE id = ?1 RETURNING data",
+                params![id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        tx.execute("DELETE FROM comments WHERE post_id = ?1", params![id.to_string()])?;
+        tx.execute("DELETE FROM post_tags WHERE post_id = ?1", params![id.to_string()])?;
+        tx.execute("DELETE FROM post_mentions WHERE post_id = ?1", params![id.to_string()])?;
+
+        let users: Vec<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT users.data FROM users JOIN user_posts ON user_posts.email = users.email
+                 WHERE user_posts.post_id = ?1",
+            )?;
+            let rows = stmt.query_map(params![id.to_string()], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        for data in users {
+            let mut user: User = from_json(&data)?;
+            if forget_post(&mut user, id) {
+                write_user(&tx, &user)?;
+            }
+        }
+        tx.commit()?;
+
+        let post: Option<Post> = data.map(|data| from_json(&data)).transpose()?;
+
+        // La connexion reste verrouillée: aucun post ne peut reprendre l'image entre-temps
+        if let Some(path) = post.as_ref().and_then(|p| p.image_path.as_deref()) {
+            let referenced: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM posts WHERE image_path = ?1)",
+                params![path],
+                |row| row.get(0),
+            )?;
+            if !referenced {
+                release_image(path);
+            }
+        }
+        Ok(post)
+    }
+
+    fn react(&self, email: &str, post_id: Uuid, reaction: Reaction) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let user_data: String = tx
+            .query_row("SELECT data FROM users WHERE email = ?1", params![email], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| anyhow!("User not found"))?;
+        let post_data: Option<String> = tx
+            .query_row("SELECT data FROM posts WHERE id = ?1", params![post_id.to_string()], |row| row.get(0))
+            .optional()?;
+        let Some(post_data) = post_data else {
+            return Ok(false);
+        };
+
+        let mut user: User = from_json(&user_data)?;
+        let mut post: Post = from_json(&post_data)?;
+        match apply_reaction(&mut post, user.reactions.get(&post_id).copied(), reaction) {
+            Some(reaction) => user.reactions.insert(post_id, reaction),
+            None => user.reactions.remove(&post_id),
+        };
+
+        write_user(&tx, &user)?;
+        write_post(&tx, &post)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn comments(&self, post_id: Uuid) -> Result<Vec<Comment>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT data FROM comments WHERE post_id = ?1 ORDER BY seq")?;
+        let rows = stmt.query_map(params![post_id.to_string()], |row| row.get::<_, String>(0))?;
+        rows.map(|data| from_json(&data?)).collect()
+    }
+
+    fn get_comment(&self, id: Uuid) -> Result<Option<Comment>> {
+        let conn = self.conn()?;
+        conn.query_row("SELECT data FROM comments WHERE id = ?1", params![id.to_string()], |row| row.get::<_, String>(0))
+            .optional()?
+            .map(|data| from_json(&data))
+            .transpose()
+    }
+
+    fn insert_comment(&self, comment: Comment) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let post_data: Option<String> = tx
+            .query_row("SELECT data FROM posts WHERE id = ?1", params![comment.post_id.to_string()], |row| row.get(0))
+            .optional()?;
+        let Some(post_data) = post_data else {
+            return Ok(false);
+        };
+
+        let mut post: Post = from_json(&post_data)?;
+        post.comment_count += 1;
+
+        tx.execute(
+            "INSERT INTO comments (id, post_id, parent_id, author, created_at, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                comment.id.to_string(),
+                comment.post_id.to_string(),
+                comment.parent_id.map(|id| id.to_string()),
+                comment.author,
+                comment.created_at.timestamp_micros(),
+                to_json(&comment)?
+            ],
+        )?;
+        write_post(&tx, &post)?;
+        tx.commit()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn open_temp() -> SqliteStorage {
+        let path = std::env::temp_dir().join(format!("lab02-sqlite-{}.db", Uuid::new_v4().simple()));
+        SqliteStorage::open(&path).unwrap()
+    }
+
+    fn user(email: &str) -> User {
+        User {
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+            email: email.to_string(),
+            user_handle: Uuid::new_v4(),
+            credentials: Vec::new(),
+            passkey: None,
+            verified: true,
+            stash: Vec::new(),
+            reactions: HashMap::new(),
+        }
+    }
+
+    fn post(author: &str) -> Post {
+        Post {
+            id: Uuid::new_v4(),
+            author: author.to_string(),
+            content: "content".to_string(),
+            image_path: None,
+            like_count: 0,
+            dislike_count: 0,
+            comment_count: 0,
+            tags: Vec::new(),
+            mentions: Vec::new(),
+            created_at: Utc::now(),
+            edited_at: None,
+        }
+    }
+
+    #[test]
+    fn delete_post_forgets_bookmarks_and_reactions() {
+        let storage = open_temp();
+        storage.insert_user(user("reader@example.com")).unwrap();
+        storage.insert_user(user("other@example.com")).unwrap();
+        let post = post("author@example.com");
+        let id = post.id;
+        storage.insert_post(post).unwrap();
+
+        storage
+            .update_user("reader@example.com", &mut |u| {
+                u.stash.push(id.to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert!(storage.react("reader@example.com", id, Reaction::Like).unwrap());
+        assert_eq!(storage.get_post(id).unwrap().unwrap().like_count, 1);
+
+        assert!(storage.delete_post(id, &mut |_| ()).unwrap().is_some());
+        let reader = storage.get_user("reader@example.com").unwrap().unwrap();
+        assert!(reader.stash.is_empty());
+        assert!(reader.reactions.is_empty());
+
+        let references: i64 = storage
+            .conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM user_posts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(references, 0);
+    }
+
+    #[test]
+    fn feed_pages_follow_cursor_order() {
+        let storage = open_temp();
+        let tag = "paging";
+        for (i, likes) in [3, 0, 3, 1, 0].into_iter().enumerate() {
+            let mut post = post("author@example.com");
+            post.tags = vec![tag.to_string()];
+            post.like_count = likes;
+            post.created_at = Utc::now() - chrono::Duration::seconds(i as i64);
+            storage.insert_post(post).unwrap();
+        }
+        storage.insert_post(post("author@example.com")).unwrap();
+
+        for sort in [Sort::New, Sort::Score] {
+            let mut expected = storage.posts().unwrap();
+            expected.retain(|p| p.tags.iter().any(|t| t == tag));
+            expected.sort_by_key(|p| std::cmp::Reverse(Cursor::of(p, sort)));
+
+            let mut seen = Vec::new();
+            let mut after = None;
+            loop {
+                let page = storage.feed(Filter::Tag(tag), sort, after.as_ref(), 2).unwrap();
+                seen.extend(page.iter().map(|p| p.id));
+                match page.last() {
+                    Some(last) => after = Some(Cursor::of(last, sort)),
+                    None => break,
+                }
+            }
+            assert_eq!(seen, expected.iter().map(|p| p.id).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Abstraction du stockage persistant.
+//! Chaque backend (YAML, SQLite) implémente [`Storage`]; le backend est choisi au démarrage.
+
+use std::path::PathBuf;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use super::comment::Comment;
+use super::email::Email;
+use super::post::{Cursor, Filter, Post, Reaction, Sort};
+use super::token::{Purpose, Token};
+use super::user::User;
+
+/// Backend de stockage disponible
+#[derive(Clone, Debug)]
+pub enum Backend {
+    /// Fichiers YAML réécrits à chaque modification, dans le dossier des données
+    Yaml { data_dir: PathBuf },
+    /// Base de données SQLite
+    Sqlite { path: PathBuf },
+}
+
+/// Opérations de persistance communes à tous les backends.
+///
+/// Les mises à jour prennent une closure appliquée de manière atomique: si elle
+/// retourne une erreur, aucune modification n'est enregistrée.
+///
+/// Les opérations touchant plusieurs enregistrements (`delete_post`, `react`,
+/// `insert_comment`) sont transactionnelles avec SQLite. Le backend YAML écrit tous les
+/// fichiers concernés avant d'en remplacer un seul; un crash entre deux remplacements peut
+/// toutefois laisser les fichiers désynchronisés (voir [`super::yaml`]).
+pub trait Storage: Send + Sync {
+    // Utilisateurs
+    fn users(&self) -> Result<Vec<User>>;
+    fn get_user(&self, email: &str) -> Result<Option<User>>;
+    fn find_user_by_handle(&self, user_handle: Uuid) -> Result<Option<User>>;
+    /// Insère un utilisateur. Retourne `false` si l'email est déjà utilisé.
+    fn insert_user(&self, user: User) -> Result<bool>;
+    /// Modifie un utilisateur. Retourne `false` s'il n'existe pas.
+    fn update_user(&self, email: &str, f: &mut dyn FnMut(&mut User) -> Result<()>) -> Result<bool>;
+
+    // Tokens, indexés par leur empreinte
+    fn insert_token(&self, hash: &str, token: Token) -> Result<()>;
+    /// Retire et retourne un token s'il existe pour l'usage demandé.
+    fn take_token(&self, hash: &str, purpose: Purpose) -> Result<Option<Token>>;
+    fn purge_expired_tokens(&self, now: DateTime<Utc>) -> Result<usize>;
+
+    // Emails
+    /// Ajoute un email en attente d'envoi
+    fn add_email(&self, to: &str, subject: &str, body: &str, html: Option<&str>) -> Result<Email>;
+    /// Tous les emails, par ordre d'ajout
+    fn emails(&self) -> Result<Vec<Email>>;
+    /// Emails en attente dont la date de nouvel essai est passée, par ordre d'ajout
+    fn due_emails(&self, now: DateTime<Utc>, limit: usize) -> Result<Vec<Email>>;
+    /// Modifie un email. Retourne `false` s'il n'existe pas.
+    fn update_email(&self, pk: u64, f: &mut dyn FnMut(&mut Email) -> Result<()>) -> Result<bool>;
+
+    // Posts
+    fn posts(&self) -> Result<Vec<Post>>;
+    fn get_post(&self, id: Uuid) -> Result<Option<Post>>;
+    /// Au plus `limit` posts correspondant à `filter`, par [`Cursor`] décroissant,
+    /// en commençant strictement après `after`
+    fn feed(&self, filter: Filter, sort: Sort, after: Option<&Cursor>, limit: usize) -> Result<Vec<Post>>;
+    fn insert_post(&self, post: Post) -> Result<()>;
+    /// Modifie un post. Retourne `false` s'il n'existe pas.
+    fn update_post(&self, id: Uuid, f: &mut dyn FnMut(&mut Post) -> Result<()>) -> Result<bool>;
+    /// Supprime et retourne un post s'il existe, ainsi que ses commentaires. Le post est
+    /// aussi retiré des favoris et réactions des utilisateurs (voir [`super::user::forget_post`]).
+    ///
+    /// Si plus aucun post ne référence son image, `release_image` est appelée avec le chemin
+    /// de l'image avant que le verrou d'écriture soit relâché: aucun post ne peut être
+    /// enregistré entre la vérification et la suppression du fichier.
+    fn delete_post(&self, id: Uuid, release_image: &mut dyn FnMut(&str)) -> Result<Option<Post>>;
+    /// Met à jour, ensemble, la réaction de l'utilisateur et les compteurs du post
+    /// (voir [`super::post::apply_reaction`]). Retourne `false` si le post n'existe pas.
+    fn react(&self, email: &str, post_id: Uuid, reaction: Reaction) -> Result<bool>;
+
+    // Commentaires
+    /// Commentaires d'un post, dans l'ordre de création
+    fn comments(&self, post_id: Uuid) -> Result<Vec<Comment>>;
+    fn get_comment(&self, id: Uuid) -> Result<Option<Comment>>;
+    /// Insère un commentaire et incrémente le compteur du post.
+    /// Retourne `false` si le post n'existe pas.
+    fn insert_comment(&self, comment: Comment) -> Result<bool>;
+}
@@ -0,0 +1,570 @@
+//! Backend de stockage en fichiers YAML.
+//! Les données sont gardées en mémoire et chaque fichier est réécrit entièrement à chaque modification.
+//! Une modification est appliquée à une copie de la base, qui ne remplace la version en mémoire
+//! qu'une fois enregistrée: un échec d'écriture ne laisse aucune modification en mémoire.
+//! Une modification touchant plusieurs fichiers les écrit tous avant d'en remplacer un seul;
+//! seul un crash entre deux renommages peut laisser les fichiers désynchronisés.
+
+use std::{
+    collections::HashMap,
+    fs::{copy, create_dir_all, read_to_string, remove_file, rename, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::consts;
+use super::comment::Comment;
+use super::email::{Email, Status};
+use super::post::{apply_reaction, Cursor, Filter, Post, Reaction, Sort};
+use super::storage::Storage;
+use super::token::{Purpose, Token};
+use super::user::{forget_post, User};
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct EmailDb {
+    pub next_pk: u64,
+    pub emails: HashMap<u64, Email>,
+}
+
+pub struct YamlStorage {
+    paths: Paths,
+    users: RwLock<HashMap<String, User>>,
+    tokens: RwLock<HashMap<String, Token>>,
+    emails: RwLock<EmailDb>,
+    posts: RwLock<Vec<Post>>,
+    comments: RwLock<Vec<Comment>>,
+}
+
+/// Chemins des fichiers YAML
+struct Paths {
+    users: PathBuf,
+    tokens: PathBuf,
+    emails: PathBuf,
+    posts: PathBuf,
+    comments: PathBuf,
+}
+
+impl YamlStorage {
+    /// Charge tous les fichiers YAML du dossier des données en mémoire
+    pub fn open(data_dir: &Path) -> Result<Self> {
+        let paths = Paths {
+            users: data_dir.join(consts::USERS_DB_FILE),
+            tokens: data_dir.join(consts::TOKENS_DB_FILE),
+            emails: data_dir.join(consts::EMAILS_DB_FILE),
+            posts: data_dir.join(consts::POSTS_DB_FILE),
+            comments: data_dir.join(consts::COMMENTS_DB_FILE),
+        };
+
+        Ok(YamlStorage {
+            users: RwLock::new(load(&paths.users)?),
+            tokens: RwLock::new(load(&paths.tokens)?),
+            emails: RwLock::new(load(&paths.emails)?),
+            posts: RwLock::new(load(&paths.posts)?),
+            comments: RwLock::new(load(&paths.comments)?),
+            paths,
+        })
+    }
+}
+
+impl Storage for YamlStorage {
+    fn users(&self) -> Result<Vec<User>> {
+        let db = self.users.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.values().cloned().collect())
+    }
+
+    fn get_user(&self, email: &str) -> Result<Option<User>> {
+        let db = self.users.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.get(email).cloned())
+    }
+
+    fn find_user_by_handle(&self, user_handle: Uuid) -> Result<Option<User>> {
+        let db = self.users.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.values().find(|u| u.user_handle == user_handle).cloned())
+    }
+
+    fn insert_user(&self, user: User) -> Result<bool> {
+        let mut db = self.users.write().or(Err(anyhow!("DB poisoned")))?;
+
+        if db.contains_key(&user.email) {
+            return Ok(false);
+        }
+
+        if db.values().any(|u| u.user_handle == user.user_handle) {
+            return Err(anyhow!("User handle already in use"));
+        }
+
+        commit(&mut *db, &self.paths.users, |db| {
+            db.insert(user.email.clone(), user);
+            Ok(true)
+        })
+    }
+
+    fn update_user(&self, email: &str, f: &mut dyn FnMut(&mut User) -> Result<()>) -> Result<bool> {
+        let mut db = self.users.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(mut user) = db.get(email).cloned() else {
+            return Ok(false);
+        };
+
+        f(&mut user)?;
+        commit(&mut *db, &self.paths.users, |db| {
+            db.insert(email.to_string(), user);
+            Ok(true)
+        })
+    }
+
+    fn insert_token(&self, hash: &str, token: Token) -> Result<()> {
+        let mut db = self.tokens.write().or(Err(anyhow!("DB poisoned")))?;
+        commit(&mut *db, &self.paths.tokens, |db| {
+            db.insert(hash.to_string(), token);
+            Ok(())
+        })
+    }
+
+    fn take_token(&self, hash: &str, purpose: Purpose) -> Result<Option<Token>> {
+        let mut db = self.tokens.write().or(Err(anyhow!("DB poisoned")))?;
+        if db.get(hash).map(|t| t.purpose) != Some(purpose) {
+            return Ok(None);
+        }
+
+        commit(&mut *db, &self.paths.tokens, |db| Ok(db.remove(hash)))
+    }
+
+    fn purge_expired_tokens(&self, now: DateTime<Utc>) -> Result<usize> {
+        let mut db = self.tokens.write().or(Err(anyhow!("DB poisoned")))?;
+        let purged = db.values().filter(|token| token.expires_at() <= now).count();
+        if purged == 0 {
+            return Ok(0);
+        }
+
+        commit(&mut *db, &self.paths.tokens, |db| {
+            db.retain(|_, token| token.expires_at() > now);
+            Ok(purged)
+        })
+    }
+
+    fn add_email(&self, to: &str, subject: &str, body: &str, html: Option<&str>) -> Result<Email> {
+        let mut db = self.emails.write().or(Err(anyhow!("DB poisoned")))?;
+
+        let pk = db.next_pk;
+        let now = Utc::now();
+        let email = Email {
+            pk,
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+            html: html.map(|html| html.to_string()),
+            status: Status::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            sent_at: None,
+        };
+
+        commit(&mut *db, &self.paths.emails, |db| {
+            db.next_pk += 1;
+            db.emails.insert(pk, email.clone());
+            Ok(email)
+        })
+    }
+
+    fn emails(&self) -> Result<Vec<Email>> {
+        let db = self.emails.read().or(Err(anyhow!("DB poisoned")))?;
+        let mut emails: Vec<Email> = db.emails.values().cloned().collect();
+        emails.sort_by_key(|e| e.pk);
+        Ok(emails)
+    }
+
+    fn due_emails(&self, now: DateTime<Utc>, limit: usize) -> Result<Vec<Email>> {
+        let db = self.emails.read().or(Err(anyhow!("DB poisoned")))?;
+        let mut due: Vec<Email> = db
+            .emails
+            .values()
+            .filter(|e| e.status == Status::Pending && e.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|e| e.pk);
+        due.truncate(limit);
+        Ok(due)
+    }
+
+    fn update_email(&self, pk: u64, f: &mut dyn FnMut(&mut Email) -> Result<()>) -> Result<bool> {
+        let mut db = self.emails.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(mut email) = db.emails.get(&pk).cloned() else {
+            return Ok(false);
+        };
+
+        f(&mut email)?;
+        commit(&mut *db, &self.paths.emails, |db| {
+            db.emails.insert(pk, email);
+            Ok(true)
+        })
+    }
+
+    fn posts(&self) -> Result<Vec<Post>> {
+        let db = self.posts.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.clone())
+    }
+
+    fn get_post(&self, id: Uuid) -> Result<Option<Post>> {
+        let db = self.posts.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.iter().find(|p| p.id == id).cloned())
+    }
+
+    fn feed(&self, filter: Filter, sort: Sort, after: Option<&Cursor>, limit: usize) -> Result<Vec<Post>> {
+        let db = self.posts.read().or(Err(anyhow!("DB poisoned")))?;
+        let mut posts: Vec<(Cursor, &Post)> = db
+            .iter()
+            .filter(|post| filter.matches(post))
+            .map(|post| (Cursor::of(post, sort), post))
+            .filter(|(key, _)| after.is_none_or(|after| key < after))
+            .collect();
+        posts.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        Ok(posts.into_iter().take(limit).map(|(_, post)| post.clone()).collect())
+    }
+
+    fn insert_post(&self, post: Post) -> Result<()> {
+        let mut db = self.posts.write().or(Err(anyhow!("DB poisoned")))?;
+        commit(&mut *db, &self.paths.posts, |db| {
+            db.push(post);
+            Ok(())
+        })
+    }
+
+    fn update_post(&self, id: Uuid, f: &mut dyn FnMut(&mut Post) -> Result<()>) -> Result<bool> {
+        let mut db = self.posts.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(index) = db.iter().position(|p| p.id == id) else {
+            return Ok(false);
+        };
+
+        let mut post = db[index].clone();
+        f(&mut post)?;
+        commit(&mut *db, &self.paths.posts, |db| {
+            db[index] = post;
+            Ok(true)
+        })
+    }
+
+    fn delete_post(&self, id: Uuid, release_image: &mut dyn FnMut(&str)) -> Result<Option<Post>> {
+        let mut users = self.users.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut db = self.posts.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut comments = self.comments.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(index) = db.iter().position(|p| p.id == id) else {
+            return Ok(None);
+        };
+
+        let mut next_posts = db.clone();
+        let post = next_posts.remove(index);
+        let mut staged = vec![stage(&next_posts, &self.paths.posts)?];
+
+        let next_comments = comments.iter().any(|c| c.post_id == id).then(|| {
+            let mut next = comments.clone();
+            next.retain(|c| c.post_id != id);
+            next
+        });
+        if let Some(next) = &next_comments {
+            staged.push(stage(next, &self.paths.comments)?);
+        }
+
+        let mut next_users = users.clone();
+        let mut changed = false;
+        for user in next_users.values_mut() {
+            changed |= forget_post(user, id);
+        }
+        if changed {
+            staged.push(stage(&next_users, &self.paths.users)?);
+        }
+
+        publish(staged)?;
+        *db = next_posts;
+        if let Some(next) = next_comments {
+            *comments = next;
+        }
+        if changed {
+            *users = next_users;
+        }
+
+        if let Some(path) = &post.image_path {
+            if !db.iter().any(|p| p.image_path.as_ref() == Some(path)) {
+                release_image(path);
+            }
+        }
+        Ok(Some(post))
+    }
+
+    fn react(&self, email: &str, post_id: Uuid, reaction: Reaction) -> Result<bool> {
+        let mut users = self.users.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut posts = self.posts.write().or(Err(anyhow!("DB poisoned")))?;
+
+        let mut user = users.get(email).cloned().ok_or_else(|| anyhow!("User not found"))?;
+        let Some(index) = posts.iter().position(|p| p.id == post_id) else {
+            return Ok(false);
+        };
+
+        let mut next_posts = posts.clone();
+        match apply_reaction(&mut next_posts[index], user.reactions.get(&post_id).copied(), reaction) {
+            Some(reaction) => user.reactions.insert(post_id, reaction),
+            None => user.reactions.remove(&post_id),
+        };
+        let mut next_users = users.clone();
+        next_users.insert(email.to_string(), user);
+
+        publish(vec![
+            stage(&next_posts, &self.paths.posts)?,
+            stage(&next_users, &self.paths.users)?,
+        ])?;
+        *posts = next_posts;
+        *users = next_users;
+        Ok(true)
+    }
+
+    fn comments(&self, post_id: Uuid) -> Result<Vec<Comment>> {
+        let db = self.comments.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.iter().filter(|c| c.post_id == post_id).cloned().collect())
+    }
+
+    fn get_comment(&self, id: Uuid) -> Result<Option<Comment>> {
+        let db = self.comments.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(db.iter().find(|c| c.id == id).cloned())
+    }
+
+    fn insert_comment(&self, comment: Comment) -> Result<bool> {
+        let mut posts = self.posts.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut comments = self.comments.write().or(Err(anyhow!("DB poisoned")))?;
+        let Some(index) = posts.iter().position(|p| p.id == comment.post_id) else {
+            return Ok(false);
+        };
+
+        let mut next_comments = comments.clone();
+        next_comments.push(comment);
+        let mut next_posts = posts.clone();
+        next_posts[index].comment_count += 1;
+
+        publish(vec![
+            stage(&next_comments, &self.paths.comments)?,
+            stage(&next_posts, &self.paths.posts)?,
+        ])?;
+        *comments = next_comments;
+        *posts = next_posts;
+        Ok(true)
+    }
+}
+
+/// Applique une modification à une copie de la base, l'enregistre, puis remplace la base
+/// en mémoire. Si l'enregistrement échoue, la base en mémoire reste inchangée.
+fn commit<T: Clone + Serialize, R>(db: &mut T, path: &Path, f: impl FnOnce(&mut T) -> Result<R>) -> Result<R> {
+    let mut next = db.clone();
+    let result = f(&mut next)?;
+    save(&next, path)?;
+    *db = next;
+    Ok(result)
+}
+
+/// Chemin de la n-ième sauvegarde d'un fichier (`users.yaml.bak.1` est la plus récente)
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".bak.{}", n));
+    PathBuf::from(name)
+}
+
+/// Fonctions de sauvegarde et chargement YAML
+///
+/// L'écriture est atomique: le contenu est écrit dans un fichier temporaire, synchronisé
+/// sur le disque, puis renommé par-dessus le fichier existant. L'ancienne version est
+/// conservée dans une rotation de sauvegardes.
+fn save<T: Serialize>(db: &T, path: &Path) -> Result<()> {
+    stage(db, path)?.publish()
+}
+
+/// Remplace les fichiers de données par leurs versions préparées, dans l'ordre
+fn publish(staged: Vec<Staged>) -> Result<()> {
+    staged.into_iter().try_for_each(Staged::publish)
+}
+
+/// Nouvelle version d'un fichier de données, écrite et synchronisée sur le disque à côté
+/// de celui-ci. Le fichier temporaire est supprimé s'il n'est pas publié.
+struct Staged {
+    tmp_path: PathBuf,
+    path: PathBuf,
+}
+
+/// Écrit la nouvelle version d'un fichier sans remplacer l'actuelle (voir [`Staged::publish`])
+fn stage<T: Serialize>(db: &T, path: &Path) -> Result<Staged> {
+    // Crée le dossier parent s'il n'existe pas
+    if let Some(parent_dir) = path.parent() {
+        if !parent_dir.exists() {
+            create_dir_all(parent_dir).or(Err(anyhow!("Failed to create directory")))?;
+        }
+    }
+
+    // Sérialise avant de toucher au disque
+    let content = serde_yaml::to_string(db).or(Err(anyhow!("Failed to serialize DB")))?;
+
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let staged = Staged {
+        tmp_path,
+        path: path.to_path_buf(),
+    };
+    let mut file = File::create(&staged.tmp_path).context("Failed to create temporary file")?;
+    file.write_all(content.as_bytes()).context("Failed to write temporary file")?;
+    file.sync_all().context("Failed to sync temporary file")?;
+    Ok(staged)
+}
+
+impl Staged {
+    /// Remplace le fichier de données par la version préparée
+    fn publish(self) -> Result<()> {
+        let path = self.path.as_path();
+
+        // Rotation des sauvegardes: .bak.1 -> .bak.2 -> ... puis copie du fichier actuel en .bak.1
+        if path.exists() {
+            for n in (1..consts::YAML_BACKUP_COUNT).rev() {
+                let from = backup_path(path, n);
+                if from.exists() {
+                    rename(&from, backup_path(path, n + 1)).context("Failed to rotate backups")?;
+                }
+            }
+            copy(path, backup_path(path, 1)).context("Failed to back up data file")?;
+        }
+
+        rename(&self.tmp_path, path).context("Failed to replace data file")?;
+
+        // Synchronise le dossier pour rendre le renommage durable
+        if let Some(parent_dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            File::open(parent_dir).and_then(|dir| dir.sync_all()).ok();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        remove_file(&self.tmp_path).ok();
+    }
+}
+
+/// Chargement d'un fichier YAML. Un fichier absent donne une base vide; un fichier
+/// illisible est une erreur afin de ne jamais écraser des données existantes.
+///
+/// [`save`] n'écrit jamais de fichier vide (une base vide s'écrit `{}` ou `[]`): un fichier
+/// vide accompagné de sauvegardes est un fichier tronqué, par exemple par un crash pendant
+/// une écriture d'une version antérieure, et non une base vide.
+fn load<T: for<'de> Deserialize<'de> + Default>(path: &Path) -> Result<T> {
+    let content = match read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let corrupt = || {
+        format!(
+            "Corrupt data file {}; restore it from {} before restarting",
+            path.display(),
+            backup_path(path, 1).display()
+        )
+    };
+
+    if content.trim().is_empty() {
+        if backup_path(path, 1).exists() {
+            return Err(anyhow!("Data file is empty")).with_context(corrupt);
+        }
+        return Ok(T::default());
+    }
+
+    serde_yaml::from_str(&content).with_context(corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lab02-yaml-{}", Uuid::new_v4().simple()));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn load_refuses_corrupt_file() {
+        let path = temp_dir().join("users.yaml");
+        write(&path, "email: [unterminated").unwrap();
+
+        assert!(load::<HashMap<String, User>>(&path).is_err());
+    }
+
+    #[test]
+    fn load_refuses_empty_file_with_backups() {
+        let path = temp_dir().join("posts.yaml");
+        save(&Vec::<Post>::new(), &path).unwrap();
+        save(&Vec::<Post>::new(), &path).unwrap();
+        assert!(backup_path(&path, 1).exists());
+
+        write(&path, "").unwrap();
+        assert!(load::<Vec<Post>>(&path).is_err());
+    }
+
+    #[test]
+    fn load_accepts_missing_or_empty_new_file() {
+        let path = temp_dir().join("posts.yaml");
+        assert!(load::<Vec<Post>>(&path).unwrap().is_empty());
+
+        write(&path, "").unwrap();
+        assert!(load::<Vec<Post>>(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unpublished_stage_leaves_data_file_untouched() {
+        let path = temp_dir().join("tokens.yaml");
+        save(&HashMap::<String, Token>::new(), &path).unwrap();
+        let before = read_to_string(&path).unwrap();
+
+        let mut tokens = HashMap::new();
+        tokens.insert("hash".to_string(), ());
+        drop(stage(&tokens, &path).unwrap());
+
+        assert_eq!(read_to_string(&path).unwrap(), before);
+        assert!(!path.with_extension("yaml.tmp").exists());
+    }
+
+    fn post_with_image(image_path: &str) -> Post {
+        Post {
+            id: Uuid::new_v4(),
+            author: "author@example.com".to_string(),
+            content: "content".to_string(),
+            image_path: Some(image_path.to_string()),
+            like_count: 0,
+            dislike_count: 0,
+            comment_count: 0,
+            tags: Vec::new(),
+            mentions: Vec::new(),
+            created_at: Utc::now(),
+            edited_at: None,
+        }
+    }
+
+    #[test]
+    fn delete_post_releases_image_once_unreferenced() {
+        let storage = YamlStorage::open(&temp_dir()).unwrap();
+        let first = post_with_image("uploads/shared.png");
+        let second = post_with_image("uploads/shared.png");
+        let (first_id, second_id) = (first.id, second.id);
+        storage.insert_post(first).unwrap();
+        storage.insert_post(second).unwrap();
+
+        let mut released = Vec::new();
+        storage.delete_post(first_id, &mut |path| released.push(path.to_string())).unwrap();
+        assert!(released.is_empty());
+
+        storage.delete_post(second_id, &mut |path| released.push(path.to_string())).unwrap();
+        assert_eq!(released, vec!["uploads/shared.png".to_string()]);
+    }
+}
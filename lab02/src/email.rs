@@ -1,12 +1,127 @@
 //! Gestion des fonctionnalités liées aux emails, telles que l'envoi et la création de liens de vérification.
+//! Les emails sont rendus à partir des templates de `templates/email/`, ajoutés à une file
+//! d'envoi persistante, puis remis en arrière-plan par le transport choisi au démarrage
+//! (voir [`transport`]). Les échecs sont réessayés avant d'être abandonnés.
 
-use anyhow::Result;
-use log::info;
-use crate::database;
+pub mod transport;
 
-/// Envoie un email simulé en ajoutant ses détails à la base de données.
-pub fn send_mail(to: &str, subject: &str, body: &str) -> Result<()> {
-    info!("Sending an email");
-    database::email::add(to, subject, body)?;
+use std::time::Duration as StdDuration;
+use anyhow::{anyhow, Context, Result};
+use chrono::Duration;
+use log::{error, info, warn};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::sync::Notify;
+use crate::{consts, database, HBS};
+use crate::database::email::Status;
+use self::transport::{Mail, MailTransport, TransportKind};
+
+/// Transport actif, initialisé une seule fois au démarrage
+static TRANSPORT: OnceCell<Box<dyn MailTransport>> = OnceCell::new();
+
+/// Réveille la file d'envoi dès qu'un email y est ajouté
+static OUTBOX_WAKEUP: Notify = Notify::const_new();
+
+/// Crée le transport choisi dans la configuration
+pub fn init(kind: TransportKind) -> Result<()> {
+    TRANSPORT
+        .set(kind.build()?)
+        .map_err(|_| anyhow!("Mail transport already initialized"))
+}
+
+fn transport() -> Result<&'static dyn MailTransport> {
+    TRANSPORT
+        .get()
+        .map(|t| t.as_ref())
+        .ok_or_else(|| anyhow!("Mail transport not initialized"))
+}
+
+/// Rend un email à partir de ses templates et l'ajoute à la file d'envoi.
+///
+/// `template` désigne les fichiers `templates/email/<template>.subject.hbs`,
+/// `<template>.text.hbs` et `<template>.html.hbs`.
+pub fn send_mail<T: Serialize>(to: &str, template: &str, data: &T) -> Result<()> {
+    let render = |part: &str| {
+        HBS.render(&format!("email/{}.{}", template, part), data)
+            .with_context(|| format!("Failed to render email template {}.{}", template, part))
+    };
+
+    let subject = render("subject")?;
+    database::email::add(to, subject.trim(), &render("text")?, Some(&render("html")?))?;
+
+    info!("Queued an email");
+    OUTBOX_WAKEUP.notify_one();
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Tente d'envoyer les emails en attente. Retourne le nombre d'emails envoyés.
+fn deliver_due() -> Result<usize> {
+    let transport = transport()?;
+    let mut sent = 0;
+
+    for email in database::email::due(consts::OUTBOX_BATCH_SIZE)? {
+        let mail = Mail {
+            to: email.to.clone(),
+            subject: email.subject.clone(),
+            text: email.body.clone(),
+            html: email.html.clone(),
+        };
+
+        match transport.send(&mail) {
+            Ok(()) => {
+                database::email::mark_sent(email.pk)?;
+                sent += 1;
+            }
+            Err(e) => match database::email::mark_failed(email.pk, &format!("{:#}", e))? {
+                Status::Dead => warn!("Giving up on email {} to {}: {:#}", email.pk, email.to, e),
+                _ => warn!("Failed to send email {} to {}, will retry: {:#}", email.pk, email.to, e),
+            },
+        }
+    }
+    Ok(sent)
+}
+
+/// Vide la file d'envoi en continu: à chaque ajout et à intervalle régulier pour les nouveaux essais
+pub async fn run_outbox() {
+    let mut interval = tokio::time::interval(StdDuration::from_secs(consts::OUTBOX_POLL_INTERVAL_SECS));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = OUTBOX_WAKEUP.notified() => (),
+        }
+
+        // Le transport SMTP est bloquant
+        match tokio::task::spawn_blocking(deliver_due).await {
+            Ok(Ok(0)) => (),
+            Ok(Ok(count)) => info!("Sent {} queued emails", count),
+            Ok(Err(e)) => error!("Failed to send queued emails: {:#}", e),
+            Err(e) => error!("Outbox task failed: {}", e),
+        }
+    }
+}
+
+/// Durée lisible pour les emails, par exemple « 30 minutes » ou « 24 hours »
+pub fn describe_duration(duration: Duration) -> String {
+    let (count, unit) = match duration.num_minutes() {
+        minutes if minutes % 60 == 0 && minutes >= 60 => (minutes / 60, "hour"),
+        minutes => (minutes, "minute"),
+    };
+    match count {
+        1 => format!("1 {}", unit),
+        count => format!("{} {}s", count, unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_duration_uses_singular_for_one() {
+        assert_eq!(describe_duration(Duration::minutes(1)), "1 minute");
+        assert_eq!(describe_duration(Duration::minutes(30)), "30 minutes");
+        assert_eq!(describe_duration(Duration::hours(1)), "1 hour");
+        assert_eq!(describe_duration(Duration::hours(24)), "24 hours");
+        assert_eq!(describe_duration(Duration::minutes(90)), "90 minutes");
+    }
+}
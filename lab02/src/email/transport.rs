@@ -0,0 +1,169 @@
+//! Transports d'emails.
+//! Chaque transport (outbox en base, journal, SMTP) implémente [`MailTransport`];
+//! le transport est choisi au démarrage.
+
+use std::fmt;
+use anyhow::{Context, Result};
+use lettre::{
+    message::{header::ContentType, MultiPart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+use log::info;
+
+/// Email prêt à être envoyé
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    /// Alternative HTML à la version texte
+    pub html: Option<String>,
+}
+
+/// Remise d'un email à son destinataire
+pub trait MailTransport: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<()>;
+}
+
+/// Sécurisation de la connexion SMTP
+#[derive(Clone, Copy, Debug)]
+pub enum SmtpTls {
+    /// STARTTLS obligatoire
+    StartTls,
+    /// Connexion en clair, pour un serveur de test local (MailHog, Mailpit)
+    None,
+}
+
+/// Transport disponible
+#[derive(Clone)]
+pub enum TransportKind {
+    /// Laisse les emails dans la file d'envoi (`emails.yaml`)
+    File,
+    /// Écrit les emails dans le journal de l'application
+    Log,
+    /// Envoie les emails à un serveur SMTP
+    Smtp {
+        host: String,
+        port: u16,
+        tls: SmtpTls,
+        /// Identifiants, si le serveur exige une authentification
+        credentials: Option<(String, String)>,
+        from: String,
+    },
+}
+
+/// Le mot de passe SMTP est masqué: la configuration peut être journalisée
+impl fmt::Debug for TransportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportKind::File => f.write_str("File"),
+            TransportKind::Log => f.write_str("Log"),
+            TransportKind::Smtp { host, port, tls, credentials, from } => f
+                .debug_struct("Smtp")
+                .field("host", host)
+                .field("port", port)
+                .field("tls", tls)
+                .field("credentials", &credentials.as_ref().map(|(user, _)| (user, "<redacted>")))
+                .field("from", from)
+                .finish(),
+        }
+    }
+}
+
+impl TransportKind {
+    /// Instancie le transport choisi
+    pub fn build(self) -> Result<Box<dyn MailTransport>> {
+        Ok(match self {
+            TransportKind::File => Box::new(FileTransport),
+            TransportKind::Log => Box::new(LogTransport),
+            TransportKind::Smtp { host, port, tls, credentials, from } => {
+                Box::new(SmtpMailTransport::new(&host, port, tls, credentials, &from)?)
+            }
+        })
+    }
+}
+
+/// Outbox en base de données, consultable par les développeurs: l'email enregistré
+/// dans la file d'envoi (`emails.yaml`) tient lieu de remise.
+pub struct FileTransport;
+
+impl MailTransport for FileTransport {
+    fn send(&self, _mail: &Mail) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Journal de l'application, sans remise réelle
+pub struct LogTransport;
+
+impl MailTransport for LogTransport {
+    fn send(&self, mail: &Mail) -> Result<()> {
+        info!("Email to {}: {}\n{}", mail.to, mail.subject, mail.text);
+        Ok(())
+    }
+}
+
+/// Remise réelle par SMTP
+pub struct SmtpMailTransport {
+    transport: SmtpTransport,
+    from: lettre::message::Mailbox,
+}
+
+impl SmtpMailTransport {
+    pub fn new(host: &str, port: u16, tls: SmtpTls, credentials: Option<(String, String)>, from: &str) -> Result<Self> {
+        let mut builder = match tls {
+            SmtpTls::StartTls => SmtpTransport::starttls_relay(host).context("Invalid SMTP host")?,
+            SmtpTls::None => SmtpTransport::builder_dangerous(host),
+        }
+        .port(port);
+
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(SmtpMailTransport {
+            transport: builder.build(),
+            from: from.parse().context("Invalid SMTP_FROM address")?,
+        })
+    }
+}
+
+impl MailTransport for SmtpMailTransport {
+    fn send(&self, mail: &Mail) -> Result<()> {
+        let builder = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse().context("Invalid recipient address")?)
+            .subject(mail.subject.as_str());
+
+        // multipart/alternative: les clients choisissent la version qu'ils savent afficher
+        let message = match &mail.html {
+            Some(html) => builder.multipart(MultiPart::alternative_plain_html(mail.text.clone(), html.clone())),
+            None => builder.header(ContentType::TEXT_PLAIN).body(mail.text.clone()),
+        }
+        .context("Failed to build email")?;
+
+        self.transport.send(&message).context("SMTP delivery failed")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_smtp_password() {
+        let kind = TransportKind::Smtp {
+            host: "smtp.example.com".to_string(),
+            port: 587,
+            tls: SmtpTls::StartTls,
+            credentials: Some(("mailer".to_string(), "hunter2".to_string())),
+            from: "noreply@example.com".to_string(),
+        };
+
+        let debug = format!("{:?}", kind);
+        assert!(debug.contains("mailer"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
@@ -3,21 +3,23 @@
 //! et démarre le serveur web avec Axum.
 
 mod backend;
+mod config;
 mod database;
 mod utils;
 mod email;
+mod media;
 mod consts;
+#[cfg(test)]
+mod testing;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{sync::Arc, time::Duration};
 use axum::Extension;
 use dotenv::dotenv;
 use handlebars::Handlebars;
-use log::info;
+use log::{error, info};
 use once_cell::sync::Lazy;
-use crate::{
-    consts::HTTP_PORT,
-    backend::handlers_auth::{load_posts_from_file, save_posts_to_file},
-};
+use crate::config::Config;
+use crate::consts::{MEDIA_GC_INTERVAL_SECS, TOKEN_SWEEP_INTERVAL_SECS, WEBAUTHN_STATE_SWEEP_INTERVAL_SECS};
 
 // Initialisation de Handlebars pour le rendu des templates
 static HBS: Lazy<Handlebars> = Lazy::new(|| {
@@ -35,30 +37,84 @@ async fn main() {
         .filter_level(log::LevelFilter::Info)
         .init();
 
-    // Charger les données des posts
-    if let Err(e) = load_posts_from_file() {
-        eprintln!("Erreur lors du chargement des posts: {}", e);
+    // Charger et valider la configuration avant tout le reste
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    config::init(config.clone()).expect("Failed to store configuration");
+    if let Err(e) = utils::webauthn::init(&config) {
+        error!("{:#}", e);
+        std::process::exit(1);
     }
 
-    // Charger les autres bases de données
-    database::user::load().ok();
-    database::email::load().ok();
+    // Ouvrir le backend de stockage choisi dans la configuration
+    database::init(config.storage).expect("Failed to open storage");
 
-    // Configurer Handlebars comme extension pour le routeur
-    let hbs = Arc::new(HBS.clone());
-    let app = backend::router::get_router().layer(Extension(hbs));
+    // Choisir le transport des emails
+    email::init(config.mail).expect("Failed to set up mail transport");
+
+    // Envoyer les emails de la file en arrière-plan
+    tokio::spawn(email::run_outbox());
+
+    // Nettoyer périodiquement les tokens expirés
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(TOKEN_SWEEP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match database::token::purge_expired() {
+                Ok(0) => (),
+                Ok(count) => info!("Purged {} expired tokens", count),
+                Err(e) => error!("Failed to purge expired tokens: {:#}", e),
+            }
+        }
+    });
 
-    // Ajouter une gestion de fin pour sauvegarder les posts
+    // Oublier périodiquement les défis d'enregistrement et de connexion abandonnés
     tokio::spawn(async {
-        tokio::signal::ctrl_c().await.unwrap();
-        if let Err(e) = save_posts_to_file() {
-            eprintln!("Erreur lors de la sauvegarde des posts: {}", e);
+        let mut interval = tokio::time::interval(Duration::from_secs(WEBAUTHN_STATE_SWEEP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let count = backend::handlers_unauth::purge_expired_states().await;
+            if count > 0 {
+                info!("Purged {} abandoned WebAuthn states", count);
+            }
         }
     });
 
+    // Supprimer périodiquement les médias qu'aucun post ne référence
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(MEDIA_GC_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let result = tokio::task::spawn_blocking(|| {
+                let referenced = database::post::scan_image_paths()?
+                    .iter()
+                    .filter_map(|path| media::id_from_path(path))
+                    .collect();
+                media::collect_garbage(&referenced)
+            })
+            .await;
+
+            match result {
+                Ok(Ok(0)) => (),
+                Ok(Ok(count)) => info!("Removed {} orphaned media files", count),
+                Ok(Err(e)) => error!("Failed to collect orphaned media: {:#}", e),
+                Err(e) => error!("Media collection task failed: {}", e),
+            }
+        }
+    });
+
+    // Configurer Handlebars comme extension pour le routeur
+    let hbs = Arc::new(HBS.clone());
+    let app = backend::router::get_router().layer(Extension(hbs));
+
     // Démarrer le serveur web
-    let addr = SocketAddr::from(([0, 0, 0, 0], HTTP_PORT));
-    info!("Listening on {}", addr);
+    let addr = config.bind_address;
+    info!("Listening on {} ({})", addr, config.public_origin);
 
     let listener = tokio::net::TcpListener::bind(addr)
         .await
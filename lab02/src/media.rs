@@ -0,0 +1,376 @@
+//! Gestion des fichiers médias uploadés.
+//! Traite les images reçues (ré-encodage, réduction, miniatures), les enregistre dans
+//! le dossier d'uploads configuré et résout les identifiants publics des médias vers les fichiers stockés.
+//!
+//! Les fichiers sont nommés d'après l'empreinte SHA-256 de leur contenu: deux uploads
+//! identiques partagent le même fichier. Un fichier n'est supprimé que lorsqu'aucun post
+//! ne le référence plus (voir [`release`] et [`collect_garbage`]). Les écritures et les
+//! suppressions sont sérialisées par [`MEDIA_LOCK`], afin qu'un fichier réutilisé par un
+//! upload ne soit pas supprimé entre sa vérification et son rafraîchissement.
+
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, read_dir, remove_file, rename, File},
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+use anyhow::{anyhow, Context, Result};
+use image::{
+    codecs::{gif::GifDecoder, jpeg::JpegEncoder},
+    AnimationDecoder, DynamicImage, ImageFormat, ImageReader, Limits,
+};
+use mime::Mime;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use crate::{config, consts};
+
+/// Qualité des JPEG ré-encodés
+const JPEG_QUALITY: u8 = 85;
+
+/// Suffixe des miniatures: `<hash>.png` a pour miniature `<hash>_thumb.png`
+const THUMBNAIL_SUFFIX: &str = "_thumb";
+
+/// Formats d'image acceptés à l'upload
+const SUPPORTED_FORMATS: [ImageFormat; 4] = [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP, ImageFormat::Gif];
+
+/// Image prête à être enregistrée
+pub struct ProcessedImage {
+    pub format: ImageFormat,
+    pub full: Vec<u8>,
+    pub thumbnail: Vec<u8>,
+}
+
+/// Format d'image correspondant à un type MIME. `None` si le format n'est pas accepté.
+pub fn format_from_mime(mime: &Mime) -> Option<ImageFormat> {
+    if mime.type_() != mime::IMAGE {
+        return None;
+    }
+    let format = ImageFormat::from_mime_type(mime.essence_str())?;
+    SUPPORTED_FORMATS.contains(&format).then_some(format)
+}
+
+/// Extension des fichiers stockés pour un format
+fn extension(format: ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("bin")
+}
+
+/// Décode une image et la ré-encode à partir des pixels seuls, dans son format d'origine.
+///
+/// Toutes les métadonnées (EXIF, GPS, commentaires) et toute donnée ajoutée après
+/// l'image sont perdues. Les images plus grandes que `MAX_IMAGE_DIMENSION` sont réduites.
+/// Les GIF animés sont refusés.
+pub fn process(bytes: &[u8], format: ImageFormat) -> Result<ProcessedImage> {
+    if !SUPPORTED_FORMATS.contains(&format) {
+        return Err(anyhow!("Unsupported image format"));
+    }
+    if format == ImageFormat::Gif && is_animated_gif(bytes)? {
+        return Err(anyhow!("Animated GIFs are not supported"));
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(consts::MAX_DECODED_DIMENSION);
+    limits.max_image_height = Some(consts::MAX_DECODED_DIMENSION);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let img = reader.decode().context("Invalid image file")?;
+
+    let img = if img.width() > consts::MAX_IMAGE_DIMENSION || img.height() > consts::MAX_IMAGE_DIMENSION {
+        img.resize(
+            consts::MAX_IMAGE_DIMENSION,
+            consts::MAX_IMAGE_DIMENSION,
+            image::imageops::FilterType::Lanczos3,
+        )
+    } else {
+        img
+    };
+    let thumbnail = img.thumbnail(consts::THUMBNAIL_DIMENSION, consts::THUMBNAIL_DIMENSION);
+
+    Ok(ProcessedImage {
+        format,
+        full: encode(&img, format)?,
+        thumbnail: encode(&thumbnail, format)?,
+    })
+}
+
+/// Un GIF est animé s'il contient plus d'une image
+fn is_animated_gif(bytes: &[u8]) -> Result<bool> {
+    let decoder = GifDecoder::new(Cursor::new(bytes)).context("Invalid image file")?;
+    Ok(decoder.into_frames().take(2).count() > 1)
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    match format {
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY)
+            .encode_image(&DynamicImage::ImageRgb8(img.to_rgb8())),
+        // L'encodeur WebP ne gère que le RGB(A) 8 bits
+        _ => DynamicImage::ImageRgba8(img.to_rgba8()).write_to(&mut Cursor::new(&mut buffer), format),
+    }
+    .context("Failed to encode image")?;
+    Ok(buffer)
+}
+
+/// Verrou des écritures et suppressions de fichiers du dossier d'uploads
+static MEDIA_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_media() -> Result<MutexGuard<'static, ()>> {
+    MEDIA_LOCK.lock().or(Err(anyhow!("Media lock poisoned")))
+}
+
+/// Enregistre une image traitée et sa miniature. Retourne le chemin stocké dans le post.
+///
+/// Si le même contenu a déjà été enregistré, le fichier existant est réutilisé.
+pub fn save(image: &ProcessedImage) -> Result<String> {
+    let _lock = lock_media()?;
+    let uploads_dir = config::get().uploads_dir.as_path();
+    if !uploads_dir.exists() {
+        create_dir_all(uploads_dir).context("Failed to create upload directory")?;
+    }
+
+    let hash = format!("{:x}", Sha256::digest(&image.full));
+    let extension = extension(image.format);
+    let path = uploads_dir.join(format!("{}.{}", hash, extension));
+    write_file(&path, &image.full)?;
+    write_file(&uploads_dir.join(format!("{}{}.{}", hash, THUMBNAIL_SUFFIX, extension)), &image.thumbnail)?;
+
+    Ok(path.display().to_string())
+}
+
+/// Écrit un fichier s'il n'existe pas encore, via un fichier temporaire afin qu'un
+/// fichier à moitié écrit ne soit jamais servi.
+fn write_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    if path.exists() {
+        // Le fichier est réutilisé: il redevient récent pour ne pas être supprimé
+        // avant que le nouveau post soit enregistré (voir [`release`])
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+            .context("Failed to refresh file")?;
+        return Ok(());
+    }
+
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = File::create(&tmp_path).context("Failed to create file")?;
+    file.write_all(bytes).context("Failed to write file")?;
+    file.sync_all().context("Failed to sync file")?;
+    rename(&tmp_path, path).context("Failed to store file")?;
+    Ok(())
+}
+
+/// Supprime les fichiers du dossier d'uploads qu'aucun post ne référence.
+///
+/// `referenced` contient les identifiants des médias encore utilisés. Les fichiers récents
+/// sont conservés: un upload est écrit sur le disque avant que son post soit enregistré.
+/// Retourne le nombre de fichiers supprimés.
+pub fn collect_garbage(referenced: &HashSet<String>) -> Result<usize> {
+    let uploads_dir = config::get().uploads_dir.as_path();
+    if !uploads_dir.exists() {
+        return Ok(0);
+    }
+
+    let _lock = lock_media()?;
+    let grace = Duration::from_secs(consts::MEDIA_GC_GRACE_SECS);
+    let mut removed = 0;
+
+    for entry in read_dir(uploads_dir).context("Failed to list upload directory")? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .unwrap_or_default();
+        if age < grace {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let orphaned = match name.strip_suffix(".tmp") {
+            // Écriture interrompue
+            Some(_) => true,
+            None => !referenced.contains(&original_id(&name)),
+        };
+
+        if orphaned {
+            remove_file(entry.path()).context("Failed to remove orphaned media")?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Supprime une image et sa miniature, qu'aucun post ne doit plus référencer.
+///
+/// Une image réutilisée récemment par un upload en cours est conservée; elle sera
+/// supprimée par [`collect_garbage`] si elle n'est finalement pas référencée.
+pub fn release(path: &str) -> Result<()> {
+    let Some(id) = id_from_path(path) else {
+        return Ok(());
+    };
+    let _lock = lock_media()?;
+    let grace = Duration::from_secs(consts::MEDIA_GC_GRACE_SECS);
+
+    for id in [thumbnail_id(&id), Some(id)].into_iter().flatten() {
+        let Some(file) = resolve(&id) else {
+            continue;
+        };
+
+        let recent = file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .is_some_and(|age| age < grace);
+        if !recent {
+            remove_file(&file).context("Failed to remove media")?;
+        }
+    }
+    Ok(())
+}
+
+/// Identifiant du média original, pour un média ou sa miniature
+fn original_id(id: &str) -> String {
+    match id.rsplit_once('.') {
+        Some((stem, extension)) => match stem.strip_suffix(THUMBNAIL_SUFFIX) {
+            Some(stem) => format!("{}.{}", stem, extension),
+            None => id.to_string(),
+        },
+        None => id.to_string(),
+    }
+}
+
+/// Identifiant de la miniature d'un média
+pub fn thumbnail_id(id: &str) -> Option<String> {
+    let (stem, extension) = id.rsplit_once('.')?;
+    Some(format!("{}{}.{}", stem, THUMBNAIL_SUFFIX, extension))
+}
+
+/// Identifiant à afficher en miniature: la miniature d'un média, ou le média lui-même
+/// pour les uploads antérieurs aux miniatures
+pub fn preview_id(id: &str) -> String {
+    preview_id_in(&config::get().uploads_dir, id)
+}
+
+fn preview_id_in(uploads_dir: &Path, id: &str) -> String {
+    thumbnail_id(id)
+        .filter(|thumbnail| resolve_in(uploads_dir, thumbnail).is_some())
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Un identifiant de média est un nom de fichier simple: pas de séparateur ni de `..`
+static MEDIA_ID_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[A-Za-z0-9_-]{1,80}\.[a-z0-9]{1,5}$").unwrap()
+});
+
+/// Identifiant public d'un média à partir du chemin stocké dans un post
+pub fn id_from_path(path: &str) -> Option<String> {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+}
+
+/// Type MIME d'un média d'après son extension. `None` si le type n'est pas servi.
+pub fn content_type(id: &str) -> Option<Mime> {
+    match Path::new(id).extension()?.to_str()? {
+        "jpg" | "jpeg" => Some(mime::IMAGE_JPEG),
+        "png" => Some(mime::IMAGE_PNG),
+        "gif" => Some(mime::IMAGE_GIF),
+        "webp" => "image/webp".parse().ok(),
+        _ => None,
+    }
+}
+
+/// Résout un identifiant de média vers son fichier, en refusant toute sortie du dossier d'uploads
+pub fn resolve(id: &str) -> Option<PathBuf> {
+    resolve_in(&config::get().uploads_dir, id)
+}
+
+fn resolve_in(uploads_dir: &Path, id: &str) -> Option<PathBuf> {
+    if !MEDIA_ID_REGEX.is_match(id) {
+        return None;
+    }
+
+    let uploads_dir = uploads_dir.canonicalize().ok()?;
+    let path = uploads_dir.join(id).canonicalize().ok()?;
+
+    if path.parent() != Some(uploads_dir.as_path()) || !path.is_file() {
+        return None;
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    /// Dossier d'uploads de test contenant `image.png`, avec `secret.png` juste à côté
+    fn uploads_dir() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("lab02-media-{}", std::process::id()));
+        let uploads_dir = root.join("uploads");
+        create_dir_all(&uploads_dir).unwrap();
+        write(uploads_dir.join("image.png"), b"image").unwrap();
+        write(root.join("secret.png"), b"secret").unwrap();
+        uploads_dir
+    }
+
+    #[test]
+    fn resolve_accepts_stored_file() {
+        let uploads_dir = uploads_dir();
+        let path = resolve_in(&uploads_dir, "image.png").unwrap();
+        assert_eq!(path, uploads_dir.canonicalize().unwrap().join("image.png"));
+    }
+
+    #[test]
+    fn resolve_rejects_missing_file() {
+        assert!(resolve_in(&uploads_dir(), "missing.png").is_none());
+    }
+
+    #[test]
+    fn resolve_rejects_traversal() {
+        let uploads_dir = uploads_dir();
+        let ids = [
+            "..",
+            "../secret.png",
+            "..%2fsecret.png",
+            "%2e%2e",
+            "%2e%2e%2fsecret.png",
+            "uploads/image.png",
+            "/etc/passwd",
+            ".png",
+        ];
+        for id in ids {
+            assert!(resolve_in(&uploads_dir, id).is_none(), "{:?} should be rejected", id);
+        }
+    }
+
+    #[test]
+    fn thumbnail_maps_back_to_original() {
+        let thumbnail = thumbnail_id("abc.png").unwrap();
+        assert_eq!(thumbnail, "abc_thumb.png");
+        assert_eq!(original_id(&thumbnail), "abc.png");
+        assert_eq!(original_id("abc.png"), "abc.png");
+    }
+
+    #[test]
+    fn preview_falls_back_to_original_without_thumbnail() {
+        let uploads_dir = uploads_dir();
+        assert_eq!(preview_id_in(&uploads_dir, "image.png"), "image.png");
+
+        write(uploads_dir.join("image_thumb.png"), b"thumbnail").unwrap();
+        assert_eq!(preview_id_in(&uploads_dir, "image.png"), "image_thumb.png");
+    }
+}
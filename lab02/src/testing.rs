@@ -0,0 +1,200 @@
+//! Outils partagés par les tests: configuration de test, client HTTP gardant le cookie de
+//! session et authentificateur WebAuthn logiciel.
+
+use std::{net::SocketAddr, sync::Arc, sync::Once};
+use axum::{body::Body, Extension, Router};
+use http::{header, Request, StatusCode};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use url::Url;
+use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
+use crate::config::{self, Config, Features};
+use crate::database::{self, storage::Backend};
+use crate::email::transport::TransportKind;
+use crate::utils::webauthn;
+use crate::HBS;
+
+/// Origine publique de la configuration de test
+pub const ORIGIN: &str = "http://localhost:8080";
+
+/// Charge la configuration de test, WebAuthn et le stockage, une seule fois par processus
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let root = std::env::temp_dir().join(format!("lab02-app-{}", std::process::id()));
+        let config = Config {
+            bind_address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            public_origin: Url::parse(ORIGIN).unwrap(),
+            allowed_origins: Vec::new(),
+            rp_id: "localhost".to_string(),
+            rp_name: "SLH Lab02".to_string(),
+            uploads_dir: root.join("uploads"),
+            storage: Backend::Yaml { data_dir: root.join("data") },
+            mail: TransportKind::Log,
+            features: Features { dev_tools: false, cors: false },
+        };
+
+        config::init(config).expect("Failed to store test configuration");
+        webauthn::init(config::get()).expect("Failed to initialize WebAuthn");
+        database::init_for_tests();
+    });
+}
+
+/// Email unique, pour que les tests ne partagent pas de compte
+pub fn unique_email(name: &str) -> String {
+    format!("{}-{}@example.com", name, uuid::Uuid::new_v4().simple())
+}
+
+/// Dernier token envoyé par email à `email` dans un lien `<prefix><token>`
+pub fn mailed_token(email: &str, prefix: &str) -> Option<String> {
+    database::email::all()
+        .unwrap()
+        .into_iter()
+        .rev()
+        .filter(|mail| mail.to == email)
+        .find_map(|mail| {
+            let start = mail.body.find(prefix)? + prefix.len();
+            let token = mail.body[start..].chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '-');
+            Some(token.collect())
+        })
+}
+
+/// Client HTTP envoyant les requêtes au routeur de l'application, avec le cookie de session
+pub struct Client {
+    app: Router,
+    cookie: Option<String>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        init();
+        Client {
+            app: crate::backend::router::get_router().layer(Extension(Arc::new(HBS.clone()))),
+            cookie: None,
+        }
+    }
+
+    /// Cookie de session actuel (`id=valeur`)
+    pub fn cookie(&self) -> Option<String> {
+        self.cookie.clone()
+    }
+
+    /// Remplace le cookie de session, par exemple pour rejouer un ancien cookie
+    pub fn set_cookie(&mut self, cookie: Option<String>) {
+        self.cookie = cookie;
+    }
+
+    pub async fn get(&mut self, path: &str) -> (StatusCode, String) {
+        self.send(Request::get(path).body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn post_json(&mut self, path: &str, body: Value) -> (StatusCode, String) {
+        let request = Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.send(request).await
+    }
+
+    async fn send(&mut self, mut request: Request<Body>) -> (StatusCode, String) {
+        if let Some(cookie) = &self.cookie {
+            request.headers_mut().insert(header::COOKIE, cookie.parse().unwrap());
+        }
+
+        let response = self.app.clone().oneshot(request).await.unwrap();
+
+        if let Some(set_cookie) = response.headers().get(header::SET_COOKIE) {
+            let set_cookie = set_cookie.to_str().unwrap();
+            let pair = set_cookie.split(';').next().unwrap_or_default().trim();
+            self.cookie = (!set_cookie.contains("Max-Age=0") && !pair.ends_with('='))
+                .then(|| pair.to_string());
+        }
+
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Inscrit un compte avec une nouvelle passkey de l'authentificateur
+    pub async fn register(&mut self, authenticator: &mut Authenticator, email: &str, reset_mode: bool) -> StatusCode {
+        let (status, body) = self.post_json("/register", json!({ "email": email, "reset_mode": reset_mode })).await;
+        if status != StatusCode::OK {
+            return status;
+        }
+        let challenge: Value = serde_json::from_str(&body).unwrap();
+
+        let response = authenticator.register(&challenge["publicKey"]);
+        let (status, _) = self
+            .post_json(
+                "/register/complete",
+                json!({
+                    "email": email,
+                    "reset_mode": reset_mode,
+                    "first_name": "Ada",
+                    "last_name": "Lovelace",
+                    "state_id": challenge["state_id"],
+                    "response": response,
+                }),
+            )
+            .await;
+        status
+    }
+
+    /// Se connecte avec une passkey de l'authentificateur
+    pub async fn login(&mut self, authenticator: &mut Authenticator, email: &str) -> StatusCode {
+        let (status, body) = self.post_json("/login", json!({ "email": email })).await;
+        if status != StatusCode::OK {
+            return status;
+        }
+        let challenge: Value = serde_json::from_str(&body).unwrap();
+
+        let response = authenticator.authenticate(&challenge["publicKey"]);
+        let (status, _) = self
+            .post_json(
+                "/login/complete",
+                json!({ "state_id": challenge["state_id"], "response": response }),
+            )
+            .await;
+        status
+    }
+}
+
+/// Authentificateur logiciel, à la place du navigateur et d'une clé de sécurité
+pub struct Authenticator {
+    inner: WebauthnAuthenticator<SoftPasskey>,
+    /// Identifiant (base64url) de la dernière passkey créée
+    credential_id: Option<Value>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Authenticator {
+            inner: WebauthnAuthenticator::new(SoftPasskey::new(true)),
+            credential_id: None,
+        }
+    }
+
+    /// Crée une passkey pour les options d'enregistrement renvoyées par le serveur
+    pub fn register(&mut self, public_key: &Value) -> RegisterPublicKeyCredential {
+        let options = serde_json::from_value(json!({ "publicKey": public_key })).unwrap();
+        let credential = self.inner.do_registration(Url::parse(ORIGIN).unwrap(), options).unwrap();
+        self.credential_id = Some(serde_json::to_value(&credential.raw_id).unwrap());
+        credential
+    }
+
+    /// Signe un défi d'authentification. Sans `allowCredentials` (connexion sans nom
+    /// d'utilisateur), la dernière passkey créée est utilisée.
+    pub fn authenticate(&mut self, public_key: &Value) -> PublicKeyCredential {
+        let mut public_key = public_key.clone();
+        if public_key.get("userVerification").is_none() {
+            public_key["userVerification"] = "required".into();
+        }
+        if public_key.get("allowCredentials").is_none() {
+            public_key["allowCredentials"] = json!([{ "type": "public-key", "id": self.credential_id }]);
+        }
+
+        let options = serde_json::from_value(json!({ "publicKey": public_key })).unwrap();
+        self.inner.do_authentication(Url::parse(ORIGIN).unwrap(), options).unwrap()
+    }
+}
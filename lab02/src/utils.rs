@@ -1,4 +1,5 @@
 //! Modules utilitaires pour diverses fonctionnalités.
 
 pub(crate) mod input;
+pub(crate) mod text;
 pub(crate) mod webauthn;
@@ -1,40 +1,63 @@
 use http::StatusCode;
-use image::GenericImageView;
+use image::ImageFormat;
 use mime::Mime;
 use regex::Regex;
 use once_cell::sync::Lazy;
 use validator::{ValidateRegex};
+use crate::{consts, media};
 
 static DISPLAY_NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"^[a-zA-ZÀ-ÖØ-öø-ÿ\s'-]{2,50}$").unwrap()
 });
 
+static PASSKEY_NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[\p{L}\p{N} ._'()-]{1,50}$").unwrap()
+});
+
 pub fn is_valid_display_name(display_name: &str) -> bool {
     display_name.validate_regex(DISPLAY_NAME_REGEX.clone())
 }
 
-/// Valide le fichier image
-pub fn validate_image_file(content_type: &str, file_bytes: &[u8]) -> axum::response::Result<()> {
+/// Valide le nom donné par l'utilisateur à une passkey
+pub fn is_valid_passkey_name(name: &str) -> bool {
+    !name.trim().is_empty() && name.validate_regex(PASSKEY_NAME_REGEX.clone())
+}
+
+/// Valide le texte d'un post (entre 1 et 200 caractères)
+pub fn is_valid_post_content(text: &str) -> bool {
+    !text.is_empty() && text.len() <= 200
+}
+
+/// Valide le texte d'un commentaire
+pub fn is_valid_comment_content(text: &str) -> bool {
+    !text.trim().is_empty() && text.chars().count() <= consts::MAX_COMMENT_LENGTH
+}
+
+/// Valide le fichier image avant son traitement (voir [`crate::media::process`]).
+/// Retourne le format réel de l'image, détecté à partir de son contenu.
+pub fn validate_image_file(content_type: &str, file_bytes: &[u8]) -> Result<ImageFormat, (StatusCode, &'static str)> {
     // Check MIME type
     let mime: Mime = content_type.parse().map_err(|_|
         (StatusCode::BAD_REQUEST, "Invalid file type")
     )?;
 
-    // Only allow jpg/jpeg
-    if mime.type_() != mime::IMAGE ||
-        (mime.subtype() != mime::JPEG) {
-        return Err((StatusCode::BAD_REQUEST, "Only .jpg files are allowed").into());
-    }
+    // Only allow jpg/png/webp/gif
+    let declared = media::format_from_mime(&mime).ok_or(
+        (StatusCode::BAD_REQUEST, "Only .jpg, .png, .webp and .gif files are allowed")
+    )?;
 
-    // Validate image using image crate
-    let img = image::load_from_memory(file_bytes)
-        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid image file"))?;
+    // Les images trop grandes en pixels sont réduites, seule la taille du fichier est limitée
+    if file_bytes.len() > consts::MAX_UPLOAD_BYTES {
+        return Err((StatusCode::BAD_REQUEST, "Image is too large. Max 10MB"));
+    }
 
-    // Optional: Add size constraints
-    let (width, height) = img.dimensions();
-    if width > 500 || height > 500 || file_bytes.len() > 10 * 1024 * 1024 {
-        return Err((StatusCode::BAD_REQUEST, "Image is too large. Max 500x500 pixels and 10MB").into());
+    // Le type déclaré par le client doit correspondre au contenu du fichier
+    let detected = image::guess_format(file_bytes).map_err(|_|
+        (StatusCode::BAD_REQUEST, "Unrecognized image format")
+    )?;
+    if detected != declared {
+        return Err((StatusCode::BAD_REQUEST, "File content does not match its declared type"));
     }
 
-    Ok(())
-}
\ No newline at end of file
+    Ok(detected)
+}
@@ -0,0 +1,49 @@
+//! Analyse du texte des posts: hashtags (`#rust`) et mentions d'utilisateurs (`@alice@example.com`).
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Un hashtag commence un mot et contient des lettres, chiffres ou `_`
+static TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|\s)#([\p{L}\p{N}_]{1,50})").unwrap()
+});
+
+/// Hashtag seul, tel qu'il apparaît dans l'URL d'un fil par hashtag
+static TAG_NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[\p{L}\p{N}_]{1,50}$").unwrap()
+});
+
+/// Une mention commence un mot et désigne un utilisateur par son email
+static MENTION_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|\s)@([A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,})").unwrap()
+});
+
+/// Valide un hashtag (sans le `#`)
+pub fn is_valid_tag(tag: &str) -> bool {
+    TAG_NAME_REGEX.is_match(tag)
+}
+
+/// Hashtags du texte, en minuscules et sans doublons, dans l'ordre d'apparition
+pub fn extract_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for capture in TAG_REGEX.captures_iter(text) {
+        let tag = capture[1].to_lowercase();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// Emails mentionnés dans le texte, sans doublons, dans l'ordre d'apparition.
+/// Les mentions ne sont pas vérifiées: un email peut ne correspondre à aucun compte.
+pub fn extract_mentions(text: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    for capture in MENTION_REGEX.captures_iter(text) {
+        let email = capture[1].to_string();
+        if !mentions.contains(&email) {
+            mentions.push(email);
+        }
+    }
+    mentions
+}
@@ -2,49 +2,73 @@
 //! Fournit des fonctions pour démarrer et compléter les processus d'enregistrement et d'authentification.
 //! Inclut également des mécanismes pour la gestion sécurisée des passkeys et des tokens de récupération.
 
-use crate::database::user::{get_passkey, set_passkey};
+use crate::config::Config;
+use crate::database::user::{self, find_by_credential, find_by_handle, record_credential_use};
 use anyhow::{anyhow, Context, Result};
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use tokio::sync::RwLock;
-use url::Url;
+use once_cell::sync::OnceCell;
 use webauthn_rs::prelude::*;
 
-// Initialisation globale de WebAuthn
-static WEBAUTHN: Lazy<Webauthn> = Lazy::new(|| {
-    let rp_id = "localhost";
-    let rp_origin = Url::parse("http://localhost:8080").expect("Invalid RP origin URL");
-
-    WebauthnBuilder::new(rp_id, &rp_origin)
-        .expect("Failed to initialize WebAuthn")
+/// Instance WebAuthn, construite une seule fois au démarrage
+static WEBAUTHN: OnceCell<Webauthn> = OnceCell::new();
+
+/// Construit l'instance WebAuthn à partir de la configuration.
+/// Une relying party invalide est ainsi rapportée au démarrage.
+pub fn init(config: &Config) -> Result<()> {
+    let builder = WebauthnBuilder::new(&config.rp_id, &config.public_origin)
+        .context("Invalid WebAuthn relying party")?
+        .rp_name(&config.rp_name);
+    let webauthn = config
+        .allowed_origins
+        .iter()
+        .fold(builder, |builder, origin| builder.append_allowed_origin(origin))
         .build()
-        .expect("Failed to build WebAuthn instance")
-});
+        .context("Failed to build WebAuthn instance")?;
+
+    WEBAUTHN
+        .set(webauthn)
+        .map_err(|_| anyhow!("WebAuthn already initialized"))
+}
 
-// Store sécurisé pour les passkeys
-pub static CREDENTIAL_STORE: Lazy<RwLock<HashMap<String, Passkey>>> = Lazy::new(Default::default);
+fn webauthn() -> &'static Webauthn {
+    WEBAUTHN.get().expect("WebAuthn not initialized")
+}
 
 // Structure pour stocker l'état d'enregistrement
 pub(crate) struct StoredRegistrationState {
     pub registration_state: PasskeyRegistration,
-    pub challenge: String,
+    pub email: String,
+    pub user_handle: Uuid,
 }
 
 /// Démarrer l'enregistrement WebAuthn
+///
+/// Les passkeys déjà enregistrées sont exclues afin que le même authentificateur
+/// ne soit pas enregistré deux fois. Une passkey découvrable (resident key) est
+/// demandée afin de permettre la connexion sans nom d'utilisateur.
 pub async fn begin_registration(
+    user_handle: Uuid,
     user_email: &str,
     user_display_name: &str,
+    exclude_credentials: Vec<CredentialID>,
 ) -> Result<(serde_json::Value, PasskeyRegistration)> {
-    let user_id = Uuid::new_v4();
+    let exclude_credentials = if exclude_credentials.is_empty() {
+        None
+    } else {
+        Some(exclude_credentials)
+    };
 
-    let (ccr, skr) = WEBAUTHN
+    let (ccr, skr) = webauthn()
         .start_passkey_registration(
-            user_id,
+            user_handle,
             user_email,
             user_display_name,
-            None,
+            exclude_credentials,
         ).context("Failed to start registration.")?;
 
+    let mut authenticator_selection = serde_json::to_value(&ccr.public_key.authenticator_selection)
+        .context("Failed to serialize authenticator selection")?;
+    authenticator_selection["residentKey"] = "preferred".into();
+
     Ok((
         serde_json::json!({
             "rp": ccr.public_key.rp,
@@ -56,7 +80,8 @@ pub async fn begin_registration(
             "challenge": ccr.public_key.challenge,
             "pubKeyCredParams": ccr.public_key.pub_key_cred_params,
             "timeout": ccr.public_key.timeout,
-            "authenticatorSelection": ccr.public_key.authenticator_selection,
+            "excludeCredentials": ccr.public_key.exclude_credentials,
+            "authenticatorSelection": authenticator_selection,
             "attestation": ccr.public_key.attestation,
         }),
         skr,
@@ -64,40 +89,40 @@ pub async fn begin_registration(
 }
 
 /// Compléter l'enregistrement WebAuthn
+///
+/// Retourne la passkey créée; c'est à l'appelant de l'associer au compte.
 pub async fn complete_registration(
     user_email: &str,
     response: &RegisterPublicKeyCredential,
     stored_state: &StoredRegistrationState,
-) -> Result<()> {
-    let passkey = WEBAUTHN
-        .finish_passkey_registration(response, &stored_state.registration_state)
-        .context("Failed to end registration")?;
+) -> Result<Passkey> {
+    if stored_state.email != user_email {
+        return Err(anyhow!("Registration state does not match user"));
+    }
 
-    let mut credential_store = CREDENTIAL_STORE.write().await;
-    credential_store.insert(user_email.to_string(), passkey.clone());
-
-    set_passkey(user_email, passkey).context("Failed to set passkey for user")?;
-
-    Ok(())
+    webauthn()
+        .finish_passkey_registration(response, &stored_state.registration_state)
+        .context("Failed to end registration")
 }
 
 /// Démarrer l'authentification WebAuthn
+///
+/// Toutes les passkeys de l'utilisateur sont proposées dans `allowCredentials`.
 pub async fn begin_authentication(
     user_email: &str,
 ) -> Result<(serde_json::Value, PasskeyAuthentication)> {
-    let pass_key = {
-        let credential_store = CREDENTIAL_STORE.read().await;
-        if let Some(pk) = credential_store.get(user_email).cloned() {
-            Some(pk)
-        }
-        else {
-            get_passkey(user_email).context("Failed to retrieve passkey from database")?
-        }
-    }.ok_or_else(|| anyhow!("Failed to retrieve passkey"))?;
-
-    let pass_keys = &[pass_key];
-    let (rcr, psk) = WEBAUTHN
-        .start_passkey_authentication(pass_keys)
+    let pass_keys: Vec<Passkey> = user::get_credentials(user_email)
+        .context("Failed to retrieve passkeys from database")?
+        .into_iter()
+        .map(|c| c.passkey)
+        .collect();
+
+    if pass_keys.is_empty() {
+        return Err(anyhow!("Failed to retrieve passkey"));
+    }
+
+    let (rcr, psk) = webauthn()
+        .start_passkey_authentication(&pass_keys)
         .context("Failed to start authentification")?;
 
     Ok((
@@ -112,14 +137,75 @@ pub async fn begin_authentication(
 }
 
 /// Compléter l'authentification WebAuthn
+///
+/// Met à jour le compteur et la date de dernière utilisation de la passkey utilisée.
+/// Le user handle renvoyé n'est pas vérifié: le défi n'accepte que les passkeys du compte,
+/// et celles migrées d'une version antérieure portent un autre identifiant.
 pub async fn complete_authentication(
+    user_email: &str,
     response: &PublicKeyCredential,
     state: &PasskeyAuthentication,
-    server_challenge: &str,
 ) -> Result<()> {
-    WEBAUTHN
+    let result = webauthn()
         .finish_passkey_authentication(response, state)
         .context("Failed to finish authentication")?;
 
+    record_credential_use(user_email, &result).context("Failed to update passkey")?;
+
     Ok(())
 }
+
+/// Démarrer une authentification sans nom d'utilisateur (passkey découvrable)
+///
+/// Le défi est émis sans connaître l'utilisateur; le navigateur le proposera via
+/// l'interface d'autocomplétion (médiation conditionnelle).
+pub async fn begin_discoverable_authentication(
+) -> Result<(serde_json::Value, DiscoverableAuthentication)> {
+    let (rcr, dsa) = webauthn()
+        .start_discoverable_authentication()
+        .context("Failed to start authentification")?;
+
+    Ok((
+        serde_json::json!({
+           "challenge": rcr.public_key.challenge,
+           "timeout": rcr.public_key.timeout,
+           "rpId": rcr.public_key.rp_id,
+           "userVerification": rcr.public_key.user_verification,
+        }),
+        dsa,
+    ))
+}
+
+/// Compléter une authentification sans nom d'utilisateur
+///
+/// Le compte est retrouvé grâce au user handle renvoyé par l'authentificateur, ou grâce
+/// à la passkey utilisée pour les passkeys migrées (voir `database::user::migrate_legacy_users`),
+/// dont le user handle est l'identifiant aléatoire choisi à leur enregistrement.
+/// Retourne l'email de l'utilisateur authentifié.
+pub async fn complete_discoverable_authentication(
+    response: &PublicKeyCredential,
+    state: DiscoverableAuthentication,
+) -> Result<String> {
+    let (user_handle, cred_id) = webauthn()
+        .identify_discoverable_authentication(response)
+        .context("Failed to identify user")?;
+
+    let user = find_by_handle(user_handle)
+        .filter(|u| u.credentials.iter().any(|c| c.passkey.cred_id().as_ref() == cred_id))
+        .or_else(|| find_by_credential(cred_id))
+        .ok_or_else(|| anyhow!("Unknown user"))?;
+    let keys: Vec<DiscoverableKey> = user
+        .credentials
+        .iter()
+        .filter(|c| c.passkey.cred_id().as_ref() == cred_id)
+        .map(|c| (&c.passkey).into())
+        .collect();
+
+    let result = webauthn()
+        .finish_discoverable_authentication(response, state, &keys)
+        .context("Failed to finish authentication")?;
+
+    record_credential_use(&user.email, &result).context("Failed to update passkey")?;
+
+    Ok(user.email)
+}